@@ -0,0 +1,178 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+
+use mixtex::{CancelToken, OrtInferenceSession, StopReason};
+
+use crate::config::AppConfig;
+use crate::decode::{run_decode, DecodeMode, DecodeQuery};
+
+/// 输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// 每个文件一行 LaTeX，多个文件时在前面加 `% 文件名` 注释行
+    Latex,
+    /// 所有文件的结果组成一个 JSON 数组
+    Json,
+    /// 每个文件一行 JSON，处理完一个写出一个
+    Jsonl,
+}
+
+/// `ocr` 子命令的参数
+#[derive(Debug, Args)]
+pub struct OcrArgs {
+    /// 图片文件或目录，可以给出多个
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// 递归处理目录中的子目录
+    #[arg(short, long)]
+    recursive: bool,
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Latex)]
+    format: OutputFormat,
+    /// 输出文件，默认写到标准输出
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[arg(long, value_enum)]
+    decode_mode: Option<DecodeMode>,
+    #[arg(long)]
+    beam_width: Option<usize>,
+    #[arg(long)]
+    max_len: Option<usize>,
+    /// 屏蔽会导致括号或环境不平衡的 token
+    #[arg(long)]
+    constrain_latex: bool,
+}
+
+impl OcrArgs {
+    fn decode_query(&self) -> DecodeQuery {
+        DecodeQuery {
+            decode_mode: self.decode_mode,
+            beam_width: self.beam_width,
+            max_len: self.max_len,
+            constrain_latex: self.constrain_latex.then_some(true),
+            ..DecodeQuery::default()
+        }
+    }
+}
+
+/// 单个文件的识别结果，成功时包含 `latex`，失败时包含 `error`
+#[derive(Serialize)]
+struct FileResult {
+    file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_reason: Option<StopReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    elapsed_ms: f64,
+}
+
+/// 不启动 HTTP 服务，直接识别文件或目录中的图片。任一文件失败时返回错误，但其余文件照常输出。
+pub fn run_ocr(config: &AppConfig, args: OcrArgs) -> anyhow::Result<()> {
+    let files = collect_images(&args.inputs, args.recursive)?;
+    if files.is_empty() {
+        anyhow::bail!("No image files found in {:?}", args.inputs);
+    }
+
+    let onnx_session = OrtInferenceSession::new(&config.model.folder, &config.model.tokenizer, config.model.intra_threads)?;
+    let decode = args.decode_query().with_defaults(&config.decode);
+
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    let mut results = Vec::new();
+    let mut failed = 0;
+    for file in &files {
+        let result = ocr_file(&onnx_session, &decode, file);
+        if let Some(error) = &result.error {
+            failed += 1;
+            eprintln!("{}: {}", result.file, error);
+        }
+        match args.format {
+            OutputFormat::Latex => {
+                if let Some(latex) = &result.latex {
+                    if files.len() > 1 {
+                        writeln!(writer, "% {}", result.file)?;
+                    }
+                    writeln!(writer, "{}", latex)?;
+                }
+            }
+            OutputFormat::Jsonl => {
+                serde_json::to_writer(&mut writer, &result)?;
+                writeln!(writer)?;
+                writer.flush()?;
+            }
+            OutputFormat::Json => results.push(result),
+        }
+    }
+    if args.format == OutputFormat::Json {
+        serde_json::to_writer_pretty(&mut writer, &results)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} files failed", failed, files.len());
+    }
+    Ok(())
+}
+
+fn ocr_file(onnx_session: &OrtInferenceSession, decode: &DecodeQuery, path: &Path) -> FileResult {
+    let start = Instant::now();
+    let result = (|| -> anyhow::Result<(String, StopReason)> {
+        let input_image = image::open(path)?;
+        let prefix_ids = decode.prefix_ids(onnx_session)?;
+        let output = run_decode(onnx_session, None, input_image, decode, &prefix_ids, CancelToken::default(), |_| {})?;
+        let latex = onnx_session.decode_tokens(&output.token_ids)?;
+        Ok((latex, output.stop_reason))
+    })();
+
+    let (latex, stop_reason, error) = match result {
+        Ok((latex, stop_reason)) => (Some(latex), Some(stop_reason), None),
+        Err(e) => (None, None, Some(format!("{:#}", e))),
+    };
+    FileResult {
+        file: path.display().to_string(),
+        latex,
+        stop_reason,
+        error,
+        elapsed_ms: start.elapsed().as_secs_f64() * 1000.0,
+    }
+}
+
+/// 展开输入中的目录，只保留扩展名是已知图片格式的文件，目录内按路径排序
+fn collect_images(inputs: &[PathBuf], recursive: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            collect_dir(input, recursive, &mut files)?;
+        } else {
+            // 直接给出的文件不检查扩展名，读取失败时作为该文件的错误输出
+            files.push(input.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn collect_dir(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                collect_dir(&path, recursive, files)?;
+            }
+        } else if image::ImageFormat::from_path(&path).is_ok() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
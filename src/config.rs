@@ -0,0 +1,272 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::time::Duration;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+
+use crate::cli::OcrArgs;
+use crate::executor::ExecutorConfig;
+use crate::i18n::Locale;
+use mixtex::BatchConfig;
+use crate::decode::DecodeDefaults;
+use crate::session_store::SessionConfig;
+
+/// 未指定 `--config` 时尝试读取的配置文件
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// 命令行参数，每一项也可以通过对应的环境变量设置；优先级为 命令行 > 环境变量 > 配置文件 > 默认值
+#[derive(Debug, Parser)]
+#[command(version, about = "MixTex LaTeX OCR inference server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// 配置文件路径，未指定时读取当前目录下存在的 config.toml
+    #[arg(long, env = "MIXTEX_CONFIG", global = true)]
+    config: Option<PathBuf>,
+    #[arg(long, env = "MIXTEX_HOST")]
+    host: Option<IpAddr>,
+    #[arg(long, env = "MIXTEX_PORT")]
+    port: Option<u16>,
+    #[arg(long, env = "MIXTEX_MODEL_FOLDER", global = true)]
+    model_folder: Option<String>,
+    #[arg(long, env = "MIXTEX_TOKENIZER", global = true)]
+    tokenizer: Option<String>,
+    /// 允许的跨域来源，逗号分隔，`*` 表示任意来源
+    #[arg(long, env = "MIXTEX_CORS_ORIGINS", value_delimiter = ',')]
+    cors_origins: Option<Vec<String>>,
+    #[arg(long, env = "MIXTEX_WORKERS")]
+    workers: Option<usize>,
+    #[arg(long, env = "MIXTEX_INTRA_THREADS", global = true)]
+    intra_threads: Option<usize>,
+    #[arg(long, env = "MIXTEX_MAX_UPLOAD_BYTES")]
+    max_upload_bytes: Option<usize>,
+}
+
+/// 不指定子命令时启动 HTTP 服务
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 识别图片文件或目录并输出结果，不启动 HTTP 服务
+    Ocr(OcrArgs),
+}
+
+/// 服务配置。所有字段都有默认值，配置文件中只需写出需要修改的部分
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub model: ModelConfig,
+    pub server: ServerConfig,
+    pub inference: InferenceConfig,
+    pub session: SessionSettings,
+    pub upload: UploadConfig,
+    /// 推理接口的默认解码参数，字段与查询参数相同（`prefix` 除外），请求中给出的参数优先
+    pub decode: DecodeDefaults,
+    pub debug: DebugConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelConfig {
+    /// 包含 encoder_model.onnx 与 decoder_model.onnx 的目录
+    pub folder: String,
+    pub tokenizer: String,
+    /// 每个 ONNX 会话的算子内线程数，0 表示由 ONNX Runtime 决定
+    pub intra_threads: usize,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            folder: "./models".to_string(),
+            tokenizer: "./tokenizer/tokenizer.json".to_string(),
+            intra_threads: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: IpAddr,
+    /// 起始端口，被占用时依次尝试后续端口
+    pub port: u16,
+    pub port_tries: u16,
+    /// 允许的跨域来源，包含 `*` 时允许任意来源
+    pub cors_origins: Vec<String>,
+    /// 请求没有给出支持的 `Accept-Language` 时使用的语言，`zh-CN` 或 `en`
+    pub locale: Locale,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8000,
+            port_tries: 20,
+            cors_origins: vec!["*".to_string()],
+            locale: Locale::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InferenceConfig {
+    pub workers: usize,
+    pub queue_capacity: usize,
+    pub retry_after_secs: u64,
+    pub max_batch_size: usize,
+    pub batch_window_ms: u64,
+    pub concurrent_batches: usize,
+}
+
+impl Default for InferenceConfig {
+    fn default() -> Self {
+        let executor = ExecutorConfig::default();
+        let batch = BatchConfig::default();
+        Self {
+            workers: executor.workers,
+            queue_capacity: executor.queue_capacity,
+            retry_after_secs: executor.retry_after.as_secs(),
+            max_batch_size: batch.max_batch_size,
+            batch_window_ms: batch.window.as_millis() as u64,
+            concurrent_batches: batch.concurrent_batches,
+        }
+    }
+}
+
+impl InferenceConfig {
+    /// 线程数小于 `max_batch_size` 时 batch 无法填满，提升到 `max_batch_size` 并给出警告
+    pub fn executor_config(&self) -> ExecutorConfig {
+        let workers = if self.workers < self.max_batch_size {
+            eprintln!(
+                "inference.workers ({}) is smaller than inference.max_batch_size ({}), using {} workers",
+                self.workers, self.max_batch_size, self.max_batch_size
+            );
+            self.max_batch_size
+        } else {
+            self.workers
+        };
+        ExecutorConfig {
+            workers,
+            queue_capacity: self.queue_capacity,
+            retry_after: Duration::from_secs(self.retry_after_secs),
+        }
+    }
+
+    pub fn batch_config(&self) -> BatchConfig {
+        BatchConfig {
+            max_batch_size: self.max_batch_size,
+            window: Duration::from_millis(self.batch_window_ms),
+            concurrent_batches: self.concurrent_batches,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionSettings {
+    pub ttl_secs: u64,
+    pub max_sessions: usize,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        let session = SessionConfig::default();
+        Self {
+            ttl_secs: session.ttl.as_secs(),
+            max_sessions: session.max_sessions,
+        }
+    }
+}
+
+impl SessionSettings {
+    pub fn session_config(&self) -> SessionConfig {
+        SessionConfig {
+            ttl: Duration::from_secs(self.ttl_secs),
+            max_sessions: self.max_sessions,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    /// 上传请求体的最大字节数
+    pub max_body_bytes: usize,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self { max_body_bytes: 10 * 1024 * 1024 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebugConfig {
+    /// 设置后每个推理任务把预处理后的图片写到 `<dump_dir>/job-<任务 id>/preprocessed.png`，默认不写
+    pub dump_dir: Option<PathBuf>,
+    /// `dump_dir` 中最多保留的任务目录数，超出时删除最早的；只清理服务创建的 `job-` 开头的目录
+    pub max_dumps: usize,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self { dump_dir: None, max_dumps: 100 }
+    }
+}
+
+impl AppConfig {
+    /// 依次读取配置文件、环境变量与命令行参数，同时返回命令行中的子命令
+    pub fn load() -> anyhow::Result<(Self, Option<Command>)> {
+        let mut cli = Cli::parse();
+        let command = cli.command.take();
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG_PATH);
+                if path.exists() {
+                    Self::from_file(&path)?
+                } else {
+                    Self::default()
+                }
+            }
+        };
+        config.apply_overrides(cli);
+        Ok((config, command))
+    }
+
+    fn from_file(path: &PathBuf) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    fn apply_overrides(&mut self, cli: Cli) {
+        if let Some(host) = cli.host {
+            self.server.host = host;
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if let Some(folder) = cli.model_folder {
+            self.model.folder = folder;
+        }
+        if let Some(tokenizer) = cli.tokenizer {
+            self.model.tokenizer = tokenizer;
+        }
+        if let Some(origins) = cli.cors_origins {
+            self.server.cors_origins = origins;
+        }
+        if let Some(workers) = cli.workers {
+            self.inference.workers = workers;
+        }
+        if let Some(intra_threads) = cli.intra_threads {
+            self.model.intra_threads = intra_threads;
+        }
+        if let Some(max_body_bytes) = cli.max_upload_bytes {
+            self.upload.max_body_bytes = max_body_bytes;
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::Instant;
+use serde::Deserialize;
+
+use mixtex::{
+    BatchScheduler, BeamSearchConfig, CancelToken, ConstrainedSelector, GenerateOptions, GenerationOutput, GreedySelector, ImageInput,
+    LatexConstraint, OrtInferenceSession, Sampler, SamplingConfig, StoppingCriteria, TokenChoice, TokenSelector,
+};
+
+/// 单次请求允许的最大生成长度
+const MAX_LEN_LIMIT: usize = 1024;
+
+/// 解码方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DecodeMode {
+    #[default]
+    Greedy,
+    Beam,
+    Sample,
+}
+
+/// 推理接口的解码参数，beam 相关参数仅在 `decode_mode=beam` 时生效，
+/// 采样相关参数仅在 `decode_mode=sample` 时生效；`n_best` 仅用于 `/n_best`。
+/// `constrain_latex=true` 时屏蔽会导致括号、`\left`/`\right` 或环境不平衡的 token；
+/// `prefix` 为用户已修正的 LaTeX 前缀，解码从前缀之后继续；
+/// `max_len`、`repeat_count`、`min_pattern_length` 控制停止条件，`repeat_count=0` 关闭重复检测
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DecodeQuery {
+    pub decode_mode: Option<DecodeMode>,
+    pub beam_width: Option<usize>,
+    pub length_penalty: Option<f32>,
+    pub early_stopping: Option<bool>,
+    pub temperature: Option<f32>,
+    pub top_k: Option<usize>,
+    pub top_p: Option<f32>,
+    pub repetition_penalty: Option<f32>,
+    pub seed: Option<u64>,
+    pub n_best: Option<usize>,
+    pub constrain_latex: Option<bool>,
+    pub prefix: Option<String>,
+    pub max_len: Option<usize>,
+    pub repeat_count: Option<usize>,
+    pub min_pattern_length: Option<usize>,
+}
+
+/// 配置文件 `[decode]` 中的默认解码参数，字段与 `DecodeQuery` 相同，但不包含只属于单次请求的 `prefix`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecodeDefaults {
+    pub decode_mode: Option<DecodeMode>,
+    pub beam_width: Option<usize>,
+    pub length_penalty: Option<f32>,
+    pub early_stopping: Option<bool>,
+    pub temperature: Option<f32>,
+    pub top_k: Option<usize>,
+    pub top_p: Option<f32>,
+    pub repetition_penalty: Option<f32>,
+    pub seed: Option<u64>,
+    pub n_best: Option<usize>,
+    pub constrain_latex: Option<bool>,
+    pub max_len: Option<usize>,
+    pub repeat_count: Option<usize>,
+    pub min_pattern_length: Option<usize>,
+}
+
+impl DecodeQuery {
+    /// 用配置中的默认参数补全请求中未给出的参数，前缀只来自请求
+    pub fn with_defaults(self, defaults: &DecodeDefaults) -> Self {
+        Self {
+            decode_mode: self.decode_mode.or(defaults.decode_mode),
+            beam_width: self.beam_width.or(defaults.beam_width),
+            length_penalty: self.length_penalty.or(defaults.length_penalty),
+            early_stopping: self.early_stopping.or(defaults.early_stopping),
+            temperature: self.temperature.or(defaults.temperature),
+            top_k: self.top_k.or(defaults.top_k),
+            top_p: self.top_p.or(defaults.top_p),
+            repetition_penalty: self.repetition_penalty.or(defaults.repetition_penalty),
+            seed: self.seed.or(defaults.seed),
+            n_best: self.n_best.or(defaults.n_best),
+            constrain_latex: self.constrain_latex.or(defaults.constrain_latex),
+            prefix: self.prefix,
+            max_len: self.max_len.or(defaults.max_len),
+            repeat_count: self.repeat_count.or(defaults.repeat_count),
+            min_pattern_length: self.min_pattern_length.or(defaults.min_pattern_length),
+        }
+    }
+
+    pub fn mode(&self) -> DecodeMode {
+        self.decode_mode.unwrap_or_default()
+    }
+
+    pub fn beam_config(&self) -> BeamSearchConfig {
+        let default = BeamSearchConfig::default();
+        BeamSearchConfig {
+            beam_width: self.beam_width.unwrap_or(default.beam_width).clamp(1, 16),
+            length_penalty: self.length_penalty.unwrap_or(default.length_penalty),
+            early_stopping: self.early_stopping.unwrap_or(default.early_stopping),
+        }
+    }
+
+    pub fn sampling_config(&self) -> SamplingConfig {
+        let default = SamplingConfig::default();
+        SamplingConfig {
+            temperature: self.temperature.unwrap_or(default.temperature),
+            top_k: self.top_k.or(default.top_k),
+            top_p: self.top_p.or(default.top_p),
+            repetition_penalty: self.repetition_penalty.unwrap_or(default.repetition_penalty),
+            seed: self.seed.or(default.seed),
+        }
+    }
+
+    pub fn stopping_criteria(&self) -> StoppingCriteria {
+        let default = StoppingCriteria::default();
+        StoppingCriteria {
+            max_len: self.max_len.unwrap_or(default.max_len).clamp(1, MAX_LEN_LIMIT),
+            repeat_count: self.repeat_count.unwrap_or(default.repeat_count),
+            min_pattern_length: self.min_pattern_length.unwrap_or(default.min_pattern_length),
+            cancel: default.cancel,
+        }
+    }
+
+    pub fn constraint(&self, onnx_session: &OrtInferenceSession) -> Option<LatexConstraint> {
+        self.constrain_latex
+            .unwrap_or(false)
+            .then(|| onnx_session.latex_constraint())
+    }
+
+    /// 贪心或采样模式下使用的 token 选择器
+    pub fn selector(&self, onnx_session: &OrtInferenceSession) -> Box<dyn TokenSelector + Send> {
+        let selector: Box<dyn TokenSelector + Send> = match self.mode() {
+            DecodeMode::Sample => Box::new(Sampler::new(self.sampling_config())),
+            _ => Box::new(GreedySelector),
+        };
+        match self.constraint(onnx_session) {
+            Some(constraint) => Box::new(ConstrainedSelector::new(selector, constraint)),
+            None => selector,
+        }
+    }
+
+    pub fn prefix_ids(&self, onnx_session: &OrtInferenceSession) -> anyhow::Result<Vec<u32>> {
+        match self.prefix.as_deref() {
+            Some(prefix) => onnx_session.encode_prefix(prefix),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// 按请求参数完成一次解码，阻塞当前线程。传入 `batcher` 时没有前缀的贪心 / 采样请求交给批处理调度器，
+/// 与其他请求合并推理；beam 模式下搜索结束后再依次回调得分最高结果的 token。
+/// `prefix_ids` 为 `prefix_ids()` 编码好的前缀，`cancel` 被触发后解码在下一步停止。
+pub fn run_decode(
+    onnx_session: &OrtInferenceSession,
+    batcher: Option<&BatchScheduler>,
+    input_image: impl Into<ImageInput>,
+    decode: &DecodeQuery,
+    prefix_ids: &[u32],
+    cancel: CancelToken,
+    mut on_token: impl FnMut(&TokenChoice),
+) -> anyhow::Result<GenerationOutput> {
+    let input_image = input_image.into();
+    let mut criteria = decode.stopping_criteria();
+    criteria.cancel = cancel;
+    if decode.mode() != DecodeMode::Beam {
+        let selector = decode.selector(onnx_session);
+        if let Some(batcher) = batcher.filter(|_| prefix_ids.is_empty()) {
+            return batcher.generate_with(input_image, selector, &criteria, on_token);
+        }
+        let options = GenerateOptions { prefix: prefix_ids.to_vec(), selector, criteria };
+        return onnx_session.generate_with(input_image, options, on_token);
+    }
+
+    let start = Instant::now();
+    let hypotheses = onnx_session.beam_search(
+        input_image,
+        &decode.beam_config(),
+        &criteria,
+        prefix_ids,
+        decode.constraint(onnx_session),
+    )?;
+    let best = hypotheses
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Beam search returned no hypothesis"))?;
+    best.choices.iter().for_each(&mut on_token);
+
+    let elapsed = start.elapsed();
+    Ok(GenerationOutput {
+        token_ids: best.token_ids,
+        stop_reason: best.stop_reason,
+        first_token_time: elapsed,
+        total_time: elapsed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_values_override_config_defaults() {
+        let defaults: DecodeDefaults = toml::from_str("decode_mode = \"beam\"\nbeam_width = 8\nmax_len = 256").unwrap();
+        let query = DecodeQuery { beam_width: Some(2), prefix: Some("x^".to_string()), ..Default::default() };
+        let merged = query.with_defaults(&defaults);
+        assert_eq!(merged.mode(), DecodeMode::Beam);
+        assert_eq!(merged.beam_width, Some(2));
+        assert_eq!(merged.max_len, Some(256));
+        assert_eq!(merged.repeat_count, None);
+        assert_eq!(merged.prefix.as_deref(), Some("x^"));
+    }
+
+    #[test]
+    fn config_defaults_reject_prefix() {
+        assert!(toml::from_str::<DecodeDefaults>("prefix = \"x\"").is_err());
+        assert!(toml::from_str::<DecodeDefaults>("max_length = 10").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// 推理线程池的规模
+#[derive(Debug, Clone)]
+pub struct ExecutorConfig {
+    /// 执行推理的线程数。贪心 / 采样请求在线程中等待批处理调度器，
+    /// 线程数不应小于批处理的最大 batch，否则 batch 无法填满
+    pub workers: usize,
+    /// 等待空闲线程的任务上限，超出时拒绝新请求
+    pub queue_capacity: usize,
+    /// 拒绝请求时建议客户端等待的时间
+    pub retry_after: Duration,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        Self {
+            workers: 8,
+            queue_capacity: 32,
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+/// 任务提交失败的原因
+#[derive(Debug)]
+pub enum SubmitError {
+    /// 队列已满
+    Busy { retry_after: Duration },
+    /// 线程池已停止
+    Stopped,
+}
+
+/// 专门执行同步 ONNX 推理的线程池，与 tokio 运行时的线程隔离，队列有界
+pub struct InferenceExecutor {
+    tasks: SyncSender<Task>,
+    retry_after: Duration,
+}
+
+impl InferenceExecutor {
+    pub fn new(config: ExecutorConfig) -> anyhow::Result<Self> {
+        let (tasks, receiver) = mpsc::sync_channel::<Task>(config.queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..config.workers.max(1) {
+            let receiver = Arc::clone(&receiver);
+            thread::Builder::new()
+                .name(format!("inference-worker-{}", index))
+                .spawn(move || run_worker(&receiver))?;
+        }
+        Ok(Self {
+            tasks,
+            retry_after: config.retry_after,
+        })
+    }
+
+    /// 提交一个任务，返回用于等待结果的 receiver。任务 panic 时 receiver 收到错误；
+    /// release 构建使用 `panic = "abort"`，panic 会直接终止整个进程
+    pub fn submit<F, R>(&self, task: F) -> Result<oneshot::Receiver<R>, SubmitError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let task: Task = Box::new(move || {
+            let _ = result_tx.send(task());
+        });
+        match self.tasks.try_send(task) {
+            Ok(()) => Ok(result_rx),
+            Err(TrySendError::Full(_)) => Err(SubmitError::Busy { retry_after: self.retry_after }),
+            Err(TrySendError::Disconnected(_)) => Err(SubmitError::Stopped),
+        }
+    }
+}
+
+fn run_worker(receiver: &Mutex<Receiver<Task>>) {
+    loop {
+        // 只在取任务时持有锁
+        let task = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let Ok(task) = task else {
+            return;
+        };
+        // 在允许 unwind 的构建（debug）中 panic 只影响当前任务，线程继续处理后续任务；
+        // release 构建使用 `panic = "abort"`，catch_unwind 不起作用
+        let _ = catch_unwind(AssertUnwindSafe(task));
+    }
+}
@@ -1,9 +1,10 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use tokio::net::TcpListener;
 
-pub async fn bind_available_port(start_port: u16, max_tries: u16) -> anyhow::Result<(TcpListener, SocketAddr)> {
-    for port in start_port..(start_port + max_tries) {
-        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+pub async fn bind_available_port(host: IpAddr, start_port: u16, max_tries: u16) -> anyhow::Result<(TcpListener, SocketAddr)> {
+    let end_port = start_port.saturating_add(max_tries.max(1));
+    for port in start_port..end_port {
+        let addr = SocketAddr::new(host, port);
         match TcpListener::bind(addr).await {
             Ok(listener) => return Ok((listener, addr)),
             Err(e) => {
@@ -13,5 +14,5 @@ pub async fn bind_available_port(start_port: u16, max_tries: u16) -> anyhow::Res
             }
         }
     }
-    anyhow::bail!("No available port found in range {}-{}", start_port, start_port + max_tries - 1);
+    anyhow::bail!("No available port found in range {}-{}", start_port, end_port - 1);
 }
\ No newline at end of file
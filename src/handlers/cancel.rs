@@ -0,0 +1,23 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::state::AppStore;
+use super::ApiError;
+
+#[derive(Serialize)]
+struct CancelResponse {
+    job_id: String,
+    cancelled: bool,
+}
+
+/// 取消一个正在进行的推理任务，任务 id 由 `/stream_inference` 的 `job` 事件给出
+pub async fn cancel_job(
+    State(app_store): State<Arc<AppStore>>,
+    Path(job_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !app_store.jobs.cancel(&job_id) {
+        return Err(ApiError::JobNotFound);
+    }
+    Ok((StatusCode::OK, Json(CancelResponse { job_id, cancelled: true })))
+}
@@ -0,0 +1,35 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use std::io::Cursor;
+use std::sync::Arc;
+
+use crate::state::AppStore;
+use super::ocr::read_image;
+use super::ApiError;
+
+/// 返回预处理后送入编码器的图片（PNG），请求格式与 `/v1/ocr` 相同，用于检查缩放、填充与归一化是否正确
+pub async fn preprocess_preview(
+    State(app_store): State<Arc<AppStore>>,
+    request: Request,
+) -> Result<impl IntoResponse, ApiError> {
+    let input_image = read_image(request).await?;
+
+    let preview_store = Arc::clone(&app_store);
+    let task = app_store.executor.submit(move || -> anyhow::Result<Vec<u8>> {
+        let preprocessor = preview_store.onnx_session.preprocessor();
+        let tensor = preprocessor.process(input_image)?;
+        let preview = preprocessor.to_image(tensor.view())?;
+        let mut png = Vec::new();
+        preview.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)?;
+        Ok(png)
+    })?;
+    let png = task
+        .await
+        .map_err(|_| ApiError::TaskAborted)?
+        .map_err(ApiError::inference)?;
+
+    Ok((StatusCode::OK, [(header::CONTENT_TYPE, "image/png")], png))
+}
@@ -0,0 +1,210 @@
+use std::time::Duration;
+use axum::{
+    extract::rejection::{BytesRejection, JsonRejection, QueryRejection},
+    extract::multipart::{MultipartError, MultipartRejection},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::executor::SubmitError;
+use crate::i18n::{Locale, Message};
+
+/// 接口返回的错误。响应体为 JSON `{code, message, details}`，`code` 是稳定的机器可读标识，
+/// `message` 面向用户并按请求的语言给出，`details` 为可选的底层错误信息
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    /// 查询参数缺失或格式错误
+    InvalidQuery(String),
+    /// 请求体无法读取或格式错误
+    InvalidBody(String),
+    /// 请求体超过上传大小限制
+    PayloadTooLarge,
+    /// multipart 请求中没有 `file` 字段
+    MissingImageField,
+    /// 图片无法解码
+    InvalidImage(String),
+    /// base64 无法解码
+    InvalidBase64(String),
+    /// 前缀无法编码为 token
+    InvalidPrefix(String),
+    SessionNotFound,
+    /// 会话中还没有图片
+    NoImage,
+    /// 会话中还没有可用的推理结果
+    NoResult,
+    JobNotFound,
+    /// 会话数据的锁已损坏
+    LockFailed,
+    /// 推理或 token 解码出错
+    InferenceFailed(String),
+    /// 推理任务没有返回结果就结束了
+    TaskAborted,
+    QueueFull { retry_after: Duration },
+    ServiceStopped,
+}
+
+#[derive(Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidQuery(_)
+            | ApiError::InvalidBody(_)
+            | ApiError::MissingImageField
+            | ApiError::InvalidBase64(_)
+            | ApiError::InvalidPrefix(_) => StatusCode::BAD_REQUEST,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::InvalidImage(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::SessionNotFound | ApiError::JobNotFound => StatusCode::NOT_FOUND,
+            ApiError::NoImage | ApiError::NoResult => StatusCode::CONFLICT,
+            ApiError::LockFailed | ApiError::InferenceFailed(_) | ApiError::TaskAborted => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::QueueFull { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::ServiceStopped => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidQuery(_) => "invalid_query",
+            ApiError::InvalidBody(_) => "invalid_body",
+            ApiError::PayloadTooLarge => "payload_too_large",
+            ApiError::MissingImageField => "missing_image_field",
+            ApiError::InvalidImage(_) => "invalid_image",
+            ApiError::InvalidBase64(_) => "invalid_base64",
+            ApiError::InvalidPrefix(_) => "invalid_prefix",
+            ApiError::SessionNotFound => "session_not_found",
+            ApiError::NoImage => "no_image",
+            ApiError::NoResult => "no_result",
+            ApiError::JobNotFound => "job_not_found",
+            ApiError::LockFailed => "lock_failed",
+            ApiError::InferenceFailed(_) => "inference_failed",
+            ApiError::TaskAborted => "task_aborted",
+            ApiError::QueueFull { .. } => "queue_full",
+            ApiError::ServiceStopped => "service_stopped",
+        }
+    }
+
+    pub fn message(&self) -> Message {
+        match self {
+            ApiError::InvalidQuery(_) => Message::InvalidQuery,
+            ApiError::InvalidBody(_) => Message::InvalidBody,
+            ApiError::PayloadTooLarge => Message::PayloadTooLarge,
+            ApiError::MissingImageField => Message::MissingImageField,
+            ApiError::InvalidImage(_) => Message::InvalidImage,
+            ApiError::InvalidBase64(_) => Message::InvalidBase64,
+            ApiError::InvalidPrefix(_) => Message::InvalidPrefix,
+            ApiError::SessionNotFound => Message::SessionNotFound,
+            ApiError::NoImage => Message::NoImage,
+            ApiError::NoResult => Message::NoResult,
+            ApiError::JobNotFound => Message::JobNotFound,
+            ApiError::LockFailed => Message::LockFailed,
+            ApiError::InferenceFailed(_) => Message::InferenceFailed,
+            ApiError::TaskAborted => Message::TaskAborted,
+            ApiError::QueueFull { .. } => Message::QueueFull,
+            ApiError::ServiceStopped => Message::ServiceStopped,
+        }
+    }
+
+    pub fn details(&self) -> Option<String> {
+        match self {
+            ApiError::InvalidQuery(details)
+            | ApiError::InvalidBody(details)
+            | ApiError::InvalidImage(details)
+            | ApiError::InvalidBase64(details)
+            | ApiError::InvalidPrefix(details)
+            | ApiError::InferenceFailed(details) => Some(details.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn body(&self, locale: Locale) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.message().text(locale),
+            details: self.details(),
+        }
+    }
+
+    /// 按指定语言生成响应，推理队列已满时额外带上 `Retry-After`
+    pub fn render(&self, locale: Locale) -> Response {
+        let body = Json(self.body(locale));
+        match self {
+            ApiError::QueueFull { retry_after } => (
+                self.status(),
+                [(header::RETRY_AFTER, retry_after.as_secs().max(1).to_string())],
+                body,
+            )
+                .into_response(),
+            _ => (self.status(), body).into_response(),
+        }
+    }
+
+    /// 读取请求体失败，超过大小限制时单独区分
+    fn body_rejection(status: StatusCode, details: String) -> Self {
+        if status == StatusCode::PAYLOAD_TOO_LARGE {
+            return ApiError::PayloadTooLarge;
+        }
+        ApiError::InvalidBody(details)
+    }
+
+    /// 推理出错，`details` 中包含完整的错误链
+    pub fn inference(error: anyhow::Error) -> Self {
+        ApiError::InferenceFailed(format!("{:#}", error))
+    }
+}
+
+/// 先以默认语言生成响应，并把错误本身放进响应扩展，由 `localize_errors` 按请求的语言重新生成
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = self.render(Locale::default());
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+impl From<SubmitError> for ApiError {
+    fn from(error: SubmitError) -> Self {
+        match error {
+            SubmitError::Busy { retry_after } => ApiError::QueueFull { retry_after },
+            SubmitError::Stopped => ApiError::ServiceStopped,
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::InvalidQuery(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::body_rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<BytesRejection> for ApiError {
+    fn from(rejection: BytesRejection) -> Self {
+        ApiError::body_rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<MultipartRejection> for ApiError {
+    fn from(rejection: MultipartRejection) -> Self {
+        ApiError::body_rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<MultipartError> for ApiError {
+    fn from(error: MultipartError) -> Self {
+        ApiError::body_rejection(error.status(), error.body_text())
+    }
+}
@@ -1,19 +1,21 @@
-use axum::{extract::State, response::IntoResponse, http::StatusCode};
+use axum::{extract::{Query, State}, extract::rejection::QueryRejection, response::IntoResponse, http::StatusCode};
 use std::sync::Arc;
 use crate::state::AppStore;
+use super::{ApiError, SessionQuery};
 
 pub async fn final_decode(
     State(app_store): State<Arc<AppStore>>,
-) -> impl IntoResponse {
-    let temp_data = app_store.temporary_data.lock().unwrap();
-    let token_id_array = temp_data.token_id_array();
+    query: Result<Query<SessionQuery>, QueryRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(query) = query?;
+    let session = app_store.sessions.get(&query.session_id).ok_or(ApiError::SessionNotFound)?;
+    let token_id_array = session.lock().map_err(|_| ApiError::LockFailed)?.token_id_array().clone();
 
     if token_id_array.is_empty() {
-        return (StatusCode::BAD_REQUEST, "貌似还没有上传图片").into_response();
+        return Err(ApiError::NoResult);
     }
 
-    let tokenizer = app_store.onnx_session.get_tokenizer();
-    let decoded_text = tokenizer.decode(token_id_array, true).unwrap_or_default();
+    let decoded_text = app_store.onnx_session.decode_tokens(&token_id_array).map_err(ApiError::inference)?;
 
-    (StatusCode::OK, format!("{}", decoded_text)).into_response()
-}
\ No newline at end of file
+    Ok((StatusCode::OK, decoded_text))
+}
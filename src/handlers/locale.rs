@@ -0,0 +1,33 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use crate::i18n::Locale;
+use crate::state::AppStore;
+use super::ApiError;
+
+/// 从 `Accept-Language` 中选出响应使用的语言，没有匹配时使用配置中的默认语言
+#[async_trait]
+impl FromRequestParts<Arc<AppStore>> for Locale {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, app_store: &Arc<AppStore>) -> Result<Self, Self::Rejection> {
+        Ok(Locale::negotiate(&parts.headers, app_store.default_locale))
+    }
+}
+
+/// 处理函数返回的 `ApiError` 以默认语言生成，这里按请求的语言重新生成错误响应
+pub async fn localize_errors(State(app_store): State<Arc<AppStore>>, request: Request, next: Next) -> Response {
+    let locale = Locale::negotiate(request.headers(), app_store.default_locale);
+    let mut response = next.run(request).await;
+    match response.extensions_mut().remove::<ApiError>() {
+        Some(error) => error.render(locale),
+        None => response,
+    }
+}
@@ -2,12 +2,34 @@ mod upload;
 mod stream;
 mod final_decode;
 mod bind_port;
+mod n_best;
+mod ocr;
+mod cancel;
+mod debug;
+mod error;
+mod locale;
+
+use serde::Deserialize;
+
+use crate::i18n::{Locale, Message};
 
 pub use upload::upload_image;
 pub use stream::stream_inference;
 pub use final_decode::final_decode;
 pub use bind_port::bind_available_port;
+pub use n_best::n_best;
+pub use ocr::ocr;
+pub use cancel::cancel_job;
+pub use debug::preprocess_preview;
+pub use error::ApiError;
+pub use locale::localize_errors;
+
+/// `/upload` 返回的会话 id，后续请求通过查询参数携带
+#[derive(Debug, Deserialize)]
+pub struct SessionQuery {
+    pub session_id: String,
+}
 
-pub async  fn greet() -> &'static str {
-    "Hello, welcome to the ONNX inference server!"
-}
\ No newline at end of file
+pub async  fn greet(locale: Locale) -> &'static str {
+    Message::Welcome.text(locale)
+}
@@ -0,0 +1,85 @@
+use axum::{extract::{Query, State}, extract::rejection::QueryRejection, response::IntoResponse, http::StatusCode, Json};
+use serde::Serialize;
+use std::sync::Arc;
+use mixtex::StopReason;
+use crate::state::AppStore;
+use crate::decode::DecodeQuery;
+use super::{ApiError, SessionQuery};
+
+#[derive(Serialize)]
+struct Candidate {
+    latex: String,
+    token_ids: Vec<u32>,
+    /// 累计 log 概率
+    log_prob: f32,
+    /// 经长度惩罚后的排序得分
+    score: f32,
+    stop_reason: StopReason,
+}
+
+#[derive(Serialize)]
+struct NBestResponse {
+    candidates: Vec<Candidate>,
+}
+
+/// 通过 beam search 返回前 N 条候选结果，得分最高的一条同时写入会话供 `/final_decode` 使用
+pub async fn n_best(
+    State(app_store): State<Arc<AppStore>>,
+    query: Result<Query<SessionQuery>, QueryRejection>,
+    decode: Result<Query<DecodeQuery>, QueryRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (Query(query), Query(decode)) = (query?, decode?);
+    let decode = decode.with_defaults(&app_store.decode_defaults);
+    let temp_data = app_store.sessions.get(&query.session_id).ok_or(ApiError::SessionNotFound)?;
+    let input_image = temp_data
+        .lock()
+        .map_err(|_| ApiError::LockFailed)?
+        .get_image()
+        .cloned()
+        .ok_or(ApiError::NoImage)?;
+
+    let n = decode.n_best.unwrap_or(3).clamp(1, 16);
+    let mut beam_config = decode.beam_config();
+    beam_config.beam_width = beam_config.beam_width.max(n);
+    let mut criteria = decode.stopping_criteria();
+
+    let onnx_session = Arc::clone(&app_store.onnx_session);
+    let prefix_ids = decode.prefix_ids(&onnx_session).map_err(|e| ApiError::InvalidPrefix(e.to_string()))?;
+    let constraint = decode.constraint(&onnx_session);
+    // 客户端断开时请求被丢弃，job 随之取消，搜索在下一步停止
+    let job = app_store.jobs.register();
+    criteria.cancel = job.cancel_token();
+    let job_id = job.job_id().to_string();
+    let dump_store = Arc::clone(&app_store);
+    let task = app_store.executor.submit(move || {
+        let input = dump_store.prepare_input(&job_id, input_image)?;
+        onnx_session.beam_search(input, &beam_config, &criteria, &prefix_ids, constraint)
+    })?;
+    let hypotheses = task
+        .await
+        .map_err(|_| ApiError::TaskAborted)?
+        .map_err(ApiError::inference)?;
+
+    if let Some(best) = hypotheses.first() {
+        if let Ok(mut guard) = temp_data.lock() {
+            guard.set_token_id_array(best.token_ids.clone());
+        }
+    }
+
+    let candidates = hypotheses
+        .into_iter()
+        .take(n)
+        .map(|hypothesis| {
+            Ok(Candidate {
+                latex: app_store.onnx_session.decode_tokens(&hypothesis.token_ids)?,
+                token_ids: hypothesis.token_ids,
+                log_prob: hypothesis.log_prob,
+                score: hypothesis.score,
+                stop_reason: hypothesis.stop_reason,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(ApiError::inference)?;
+
+    Ok((StatusCode::OK, Json(NBestResponse { candidates })))
+}
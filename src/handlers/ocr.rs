@@ -0,0 +1,118 @@
+use axum::{
+    body::Bytes,
+    extract::{rejection::QueryRejection, FromRequest, Multipart, Query, Request, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use mixtex::StopReason;
+use crate::decode::{run_decode, DecodeQuery};
+use crate::state::AppStore;
+use super::ApiError;
+
+/// JSON 请求体，`image` 为 base64 编码的图片，可带 `data:image/...;base64,` 前缀
+#[derive(Deserialize)]
+struct OcrJsonBody {
+    image: String,
+}
+
+#[derive(Serialize)]
+struct OcrTiming {
+    /// 从开始推理到第一个 token 的耗时（含预处理与编码器）
+    first_token_ms: f64,
+    total_ms: f64,
+    tokens_per_second: f64,
+}
+
+#[derive(Serialize)]
+struct OcrResponse {
+    latex: String,
+    token_ids: Vec<u32>,
+    stop_reason: StopReason,
+    timing: OcrTiming,
+}
+
+/// 无状态的一次性识别接口：图片可以是 multipart 的 `file` 字段、原始字节或 base64 JSON
+pub async fn ocr(
+    State(app_store): State<Arc<AppStore>>,
+    decode: Result<Query<DecodeQuery>, QueryRejection>,
+    request: Request,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(decode) = decode?;
+    let decode = decode.with_defaults(&app_store.decode_defaults);
+    let input_image = read_image(request).await?;
+
+    let prefix_ids = decode
+        .prefix_ids(&app_store.onnx_session)
+        .map_err(|e| ApiError::InvalidPrefix(e.to_string()))?;
+
+    // 客户端断开时请求被丢弃，job 随之取消，解码在下一步停止
+    let job = app_store.jobs.register();
+    let cancel = job.cancel_token();
+    let job_id = job.job_id().to_string();
+    let decode_store = Arc::clone(&app_store);
+    let task = app_store.executor.submit(move || {
+        let input = decode_store.prepare_input(&job_id, input_image)?;
+        run_decode(&decode_store.onnx_session, Some(&decode_store.batcher), input, &decode, &prefix_ids, cancel, |_| {})
+    })?;
+    let output = task
+        .await
+        .map_err(|_| ApiError::TaskAborted)?
+        .map_err(ApiError::inference)?;
+
+    let latex = app_store.onnx_session.decode_tokens(&output.token_ids).map_err(ApiError::inference)?;
+    let total_secs = output.total_time.as_secs_f64();
+    let generated = output.token_ids.len().saturating_sub(1) as f64;
+    let timing = OcrTiming {
+        first_token_ms: output.first_token_time.as_secs_f64() * 1000.0,
+        total_ms: total_secs * 1000.0,
+        tokens_per_second: if total_secs > 0.0 { generated / total_secs } else { 0.0 },
+    };
+
+    let body = OcrResponse {
+        latex,
+        token_ids: output.token_ids,
+        stop_reason: output.stop_reason,
+        timing,
+    };
+    Ok((StatusCode::OK, Json(body)))
+}
+
+/// 按 Content-Type 读取请求中的图片
+pub(super) async fn read_image(request: Request) -> Result<image::DynamicImage, ApiError> {
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+
+    let data: Vec<u8> = if content_type.starts_with("multipart/form-data") {
+        let mut multipart = Multipart::from_request(request, &()).await?;
+        loop {
+            match multipart.next_field().await? {
+                Some(field) if field.name() == Some("file") => break field.bytes().await?.to_vec(),
+                Some(_) => continue,
+                None => return Err(ApiError::MissingImageField),
+            }
+        }
+    } else if content_type.starts_with("application/json") {
+        let Json(body) = Json::<OcrJsonBody>::from_request(request, &()).await?;
+        // 兼容 data URL
+        let encoded = match body.image.split_once("base64,") {
+            Some((_, encoded)) => encoded,
+            None => body.image.as_str(),
+        };
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| ApiError::InvalidBase64(e.to_string()))?
+    } else {
+        Bytes::from_request(request, &()).await?.to_vec()
+    };
+
+    image::load_from_memory(&data).map_err(|e| ApiError::InvalidImage(e.to_string()))
+}
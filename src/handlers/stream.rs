@@ -1,96 +1,196 @@
-use axum::{extract::State, response::sse::{Event, Sse}};
-use std::{convert::Infallible, pin::Pin, sync::Arc};
+use axum::{extract::{Query, State}, extract::rejection::QueryRejection, response::{sse::{Event, Sse}, IntoResponse, Response}};
+use std::{convert::Infallible, sync::Arc};
 use tokio_stream::wrappers::ReceiverStream;
 use futures::StreamExt;
+use serde::Serialize;
 
+use mixtex::{CancelToken, StopReason, TokenChoice};
+use crate::decode::{run_decode, DecodeQuery};
+use crate::i18n::{Locale, Message};
 use crate::state::AppStore;
-use crate::check_repetition;
+use super::{ApiError, SessionQuery};
+
+/// 每个 token 对应的 SSE 事件数据
+#[derive(Serialize)]
+struct TokenEvent {
+    token_id: u32,
+    text: String,
+    /// 被选中 token 的 log 概率
+    log_prob: f32,
+    /// 在该步所有 token 中的排名，0 表示概率最高
+    rank: usize,
+    /// 与另一个最可能 token 的 log 概率之差
+    margin: f32,
+}
+
+impl TokenEvent {
+    fn new(choice: &TokenChoice, text: String) -> Self {
+        Self {
+            token_id: choice.token_id,
+            text,
+            log_prob: choice.log_prob,
+            rank: choice.rank,
+            margin: choice.margin,
+        }
+    }
+}
+
+/// 流结束的原因，除生成本身的停止原因外还包括推理出错
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FinishReason {
+    Eos,
+    MaxLen,
+    Repetition,
+    Cancelled,
+    Error,
+}
+
+impl From<StopReason> for FinishReason {
+    fn from(reason: StopReason) -> Self {
+        match reason {
+            StopReason::Eos => FinishReason::Eos,
+            StopReason::MaxLen => FinishReason::MaxLen,
+            StopReason::Repetition => FinishReason::Repetition,
+            StopReason::Cancelled => FinishReason::Cancelled,
+        }
+    }
+}
+
+/// 每个流最后发送的 `done` 事件
+#[derive(Serialize)]
+struct DoneEvent {
+    stop_reason: FinishReason,
+    /// 本次生成的 token 数（不含 BOS 与前缀）
+    generated_tokens: usize,
+}
+
+/// 每个流最先发送的 `job` 事件，任务 id 可用于 `POST /cancel/{job}`
+#[derive(Serialize)]
+struct JobEvent {
+    job_id: String,
+}
+
+/// 推送到 SSE 流中的消息：首先以 `job` 事件给出任务 id，token 以 JSON 作为默认事件发送，
+/// 错误以 `error` 事件发送（与接口错误相同的 `{code, message, details}`），最后以 `done` 事件说明结束原因
+enum StreamMessage {
+    Job(JobEvent),
+    Token(TokenEvent),
+    Error(ApiError),
+    Done(DoneEvent),
+}
+
+impl StreamMessage {
+    fn into_event(self, locale: Locale) -> Event {
+        match self {
+            StreamMessage::Job(job) => Event::default()
+                .event("job")
+                .json_data(&job)
+                .unwrap_or_else(|_| Event::default().event("job")),
+            StreamMessage::Token(token) => Event::default()
+                .json_data(&token)
+                .unwrap_or_else(|_| Event::default().event("error").data(Message::SerializationFailed.text(locale))),
+            StreamMessage::Error(error) => Event::default()
+                .event("error")
+                .json_data(error.body(locale))
+                .unwrap_or_else(|_| Event::default().event("error")),
+            StreamMessage::Done(done) => Event::default()
+                .event("done")
+                .json_data(&done)
+                .unwrap_or_else(|_| Event::default().event("done")),
+        }
+    }
+}
+
+/// 随响应流一起被丢弃时取消任务；任务已正常结束时取消没有影响
+struct CancelOnDrop(CancelToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
 
 pub async fn stream_inference(
     State(app_store): State<Arc<AppStore>>,
-) -> Sse<Pin<Box<dyn futures::Stream<Item = Result<Event, Infallible>> + Send>>> {
-    let temp_data = Arc::clone(&app_store.temporary_data);
-    
+    locale: Locale,
+    query: Result<Query<SessionQuery>, QueryRejection>,
+    decode: Result<Query<DecodeQuery>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let (Query(query), Query(decode)) = (query?, decode?);
+    let decode = decode.with_defaults(&app_store.decode_defaults);
+    let temp_data = app_store.sessions.get(&query.session_id).ok_or(ApiError::SessionNotFound)?;
+
     // 1. 先获取并克隆图像数据
     let input_image = {
-        let guard = temp_data.lock().unwrap();
-        match guard.get_image() {
-            Some(img) => img.clone(),
-            None => {
-                return Sse::new(tokio_stream::empty::<Result<Event, Infallible>>().boxed());
-            }
-        }
+        let guard = temp_data.lock().map_err(|_| ApiError::LockFailed)?;
+        guard.get_image().cloned().ok_or(ApiError::NoImage)?
     }; // MutexGuard在这里被释放
 
     let (tx, rx) = tokio::sync::mpsc::channel(16);
-    let onnx_session = Arc::clone(&app_store.onnx_session);
-    let temp_data = Arc::clone(&temp_data); // 克隆一份用于发送到新任务
+    let prefix_ids = decode
+        .prefix_ids(&app_store.onnx_session)
+        .map_err(|e| ApiError::InvalidPrefix(e.to_string()))?;
+
+    // 2. 登记任务；SSE 连接关闭（响应流被丢弃）时由 CancelOnDrop 立即取消，不必等到下一次发送失败
+    let job = app_store.jobs.register();
+    let cancel = job.cancel_token();
+    let _ = tx.try_send(StreamMessage::Job(JobEvent { job_id: job.job_id().to_string() }));
+    let on_close = CancelOnDrop(cancel.clone());
 
-    tokio::spawn(async move {
-        let tokenizer = onnx_session.get_tokenizer();
-        let eos_token_id = tokenizer.token_to_id("</s>").unwrap_or(30000);
-        let bos_token_id = tokenizer.token_to_id("<s>").unwrap_or(0);
-        let max_len = 512;
+    // 3. 推理是同步的 CPU 密集任务，交给推理线程池执行，通过 channel 推送 token
+    let executor = Arc::clone(&app_store.executor);
+    let task = executor.submit(move || {
+        // job 随闭包一起在推理结束后才注销
+        let tokenizer = app_store.onnx_session.get_tokenizer();
+        let mut generated_tokens = 0;
 
-        let (decoder_outputs, next_token_id, encoder_hidden_states) = match onnx_session.init_inference(input_image) {
-            Ok(res) => res,
+        let result = app_store.prepare_input(job.job_id(), input_image).and_then(|input| {
+            run_decode(&app_store.onnx_session, Some(&app_store.batcher), input, &decode, &prefix_ids, cancel.clone(), |choice| {
+                generated_tokens += 1;
+                let text = tokenizer.decode(&[choice.token_id], true).unwrap_or_default();
+                if tx.blocking_send(StreamMessage::Token(TokenEvent::new(choice, text))).is_err() {
+                    cancel.cancel();
+                }
+            })
+        });
+        let output = match result {
+            Ok(output) => output,
             Err(e) => {
-                let _ = tx.send(format!("初始化推理失败: {:?}", e)).await;
+                let _ = tx.blocking_send(StreamMessage::Error(ApiError::inference(e)));
+                let done = DoneEvent { stop_reason: FinishReason::Error, generated_tokens };
+                let _ = tx.blocking_send(StreamMessage::Done(done));
                 return;
             }
         };
-        let next_token = tokenizer.decode(&vec![next_token_id], true).unwrap_or_default();
-        let _ = tx.send(next_token.clone()).await;
-        let mut token_id_array = vec![bos_token_id, next_token_id];
-
-        let mut decoder_inputs = decoder_outputs;
-        let mut encoder_input = encoder_hidden_states;
-        let mut input_token_id = next_token_id;
-
-        for _i in 0..max_len {
-            let (decoder_outputs, next_token_id, encoder_hidden_states) =
-                match onnx_session.single_inference(decoder_inputs, input_token_id, encoder_input) {
-                    Ok(res) => res,
-                    Err(e) => {
-                        let _ = tx.send(format!("单次推理失败: {:?}", e)).await;
-                        break;
-                    }
-                };
-
-            token_id_array.push(next_token_id);
-            let is_stop = check_repetition(&token_id_array, 10);
-            if is_stop {
-                let _ = tx.send("\n\n推理异常，停止推理".to_string()).await;
-                break;
-            }
+        let stop_reason = output.stop_reason;
 
-            let next_token = tokenizer.decode(&vec![next_token_id], true).unwrap_or_default();
-            let _ = tx.send(next_token.clone()).await;
-            if next_token_id == eos_token_id {
-                break;
-            }
-            decoder_inputs = decoder_outputs;
-            encoder_input = encoder_hidden_states;
-            input_token_id = next_token_id;
-        }
         // 将 token_id_array 存储到临时数据中
         // 在发送消息之前先完成数据更新
         // 将锁的获取和使用放在最小范围内
-        {
-            if let Ok(mut guard) = temp_data.lock() {
-                guard.set_token_id_array(token_id_array);
+        let stored = match temp_data.lock() {
+            Ok(mut guard) => {
+                guard.set_token_id_array(output.token_ids);
+                true
             }
-        } // 锁在这里被释放
+            Err(_) => false,
+        }; // 锁在这里被释放
 
-        // 3. 错误消息的发送移到锁释放之后
-        if temp_data.lock().is_err() {
-            let _ = tx.send("临时数据锁定失败".to_string()).await;
+        // 4. 错误消息的发送移到锁释放之后
+        if !stored {
+            let _ = tx.blocking_send(StreamMessage::Error(ApiError::LockFailed));
         }
-    });
 
+        let done = DoneEvent { stop_reason: stop_reason.into(), generated_tokens };
+        let _ = tx.blocking_send(StreamMessage::Done(done));
+    })?;
+    // token 通过 channel 推送，不需要等待任务的返回值
+    drop(task);
 
-    let stream = ReceiverStream::new(rx)
-        .map(|token| Ok(Event::default().data(token)))
-        .boxed();
-    Sse::new(stream)
-}
\ No newline at end of file
+    // 推理线程持有唯一的 Sender，发送 done 后随闭包一起释放，流随之结束
+    let stream = ReceiverStream::new(rx).map(move |message: StreamMessage| {
+        let _on_close = &on_close;
+        Ok::<Event, Infallible>(message.into_event(locale))
+    });
+    Ok(Sse::new(stream).into_response())
+}
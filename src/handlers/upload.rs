@@ -1,31 +1,38 @@
-use axum::{extract::{Multipart, State}, response::IntoResponse, http::StatusCode};
+use axum::{extract::{multipart::MultipartRejection, Multipart, State}, response::IntoResponse, http::StatusCode, Json};
+use serde::Serialize;
 use std::sync::Arc;
+use crate::temporary_img::TemporaryData;
+use crate::i18n::{Locale, Message};
 use crate::state::AppStore;
+use super::ApiError;
+
+#[derive(Serialize)]
+struct UploadResponse {
+    session_id: String,
+    message: &'static str,
+}
 
 pub async fn upload_image(
     State(app_store): State<Arc<AppStore>>,
-    mut multipart: Multipart,
-) -> impl IntoResponse {
-    while let Some(field) = multipart.next_field().await.unwrap() {
+    locale: Locale,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    // 缺少或错误的 boundary 同样返回统一格式的错误
+    let mut multipart = multipart?;
+    while let Some(field) = multipart.next_field().await? {
         let name = field.name().unwrap_or("").to_string();
 
         if name == "file" {
-            let data = field.bytes().await.unwrap();
-            match image::load_from_memory(&data) {
-                Ok(img) => {
-                    // 注意这里要 lock
-                    if let Ok(mut temp_data) = app_store.temporary_data.lock() {
-                        temp_data.set_image(img);
-                        return (StatusCode::OK, "图片上传成功").into_response();
-                    } else {
-                        return (StatusCode::INTERNAL_SERVER_ERROR, "数据锁定失败").into_response();
-                    }
-                }
-                Err(e) => {
-                    return (StatusCode::BAD_REQUEST, format!("图片解码失败: {}", e)).into_response();
-                }
-            }
+            let data = field.bytes().await?;
+            let img = image::load_from_memory(&data).map_err(|e| ApiError::InvalidImage(e.to_string()))?;
+            // 每次上传创建独立会话，避免不同客户端互相覆盖
+            let session_id = app_store
+                .sessions
+                .create(TemporaryData::with_image(img))
+                .map_err(|_| ApiError::LockFailed)?;
+            let body = UploadResponse { session_id, message: Message::UploadSucceeded.text(locale) };
+            return Ok((StatusCode::OK, Json(body)));
         }
     }
-    (StatusCode::BAD_REQUEST, "没有找到图片字段").into_response()
+    Err(ApiError::MissingImageField)
 }
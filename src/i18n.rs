@@ -0,0 +1,140 @@
+use axum::http::{header, HeaderMap};
+use serde::Deserialize;
+
+/// 面向用户的消息所使用的语言
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "zh-CN", alias = "zh")]
+    ZhCn,
+    #[serde(rename = "en", alias = "en-US")]
+    En,
+}
+
+impl Locale {
+    /// 按 `Accept-Language` 中的权重选择第一个支持的语言，没有匹配时使用 `default`
+    pub fn negotiate(headers: &HeaderMap, default: Locale) -> Locale {
+        let Some(accept) = headers.get(header::ACCEPT_LANGUAGE).and_then(|value| value.to_str().ok()) else {
+            return default;
+        };
+        let mut ranges: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // 稳定排序，权重相同时保持客户端给出的顺序
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges
+            .into_iter()
+            .find_map(|(tag, _)| Self::from_tag(tag, default))
+            .unwrap_or(default)
+    }
+
+    fn from_tag(tag: &str, default: Locale) -> Option<Locale> {
+        let primary = tag.split(['-', '_']).next()?.to_ascii_lowercase();
+        match primary.as_str() {
+            "zh" => Some(Locale::ZhCn),
+            "en" => Some(Locale::En),
+            "*" => Some(default),
+            _ => None,
+        }
+    }
+}
+
+/// 消息目录，每条消息都有中文与英文两个版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    Welcome,
+    UploadSucceeded,
+    InvalidQuery,
+    InvalidBody,
+    PayloadTooLarge,
+    MissingImageField,
+    InvalidImage,
+    InvalidBase64,
+    InvalidPrefix,
+    SessionNotFound,
+    NoImage,
+    NoResult,
+    JobNotFound,
+    LockFailed,
+    InferenceFailed,
+    TaskAborted,
+    QueueFull,
+    ServiceStopped,
+    SerializationFailed,
+}
+
+impl Message {
+    pub fn text(self, locale: Locale) -> &'static str {
+        let (zh, en) = match self {
+            Message::Welcome => ("你好，欢迎使用 ONNX 推理服务！", "Hello, welcome to the ONNX inference server!"),
+            Message::UploadSucceeded => ("图片上传成功", "Image uploaded"),
+            Message::InvalidQuery => ("查询参数错误", "Invalid query parameters"),
+            Message::InvalidBody => ("请求体格式错误", "Invalid request body"),
+            Message::PayloadTooLarge => ("上传内容超过大小限制", "Request body exceeds the upload limit"),
+            Message::MissingImageField => ("没有找到图片字段", "No image field found"),
+            Message::InvalidImage => ("图片解码失败", "Failed to decode the image"),
+            Message::InvalidBase64 => ("base64 解码失败", "Failed to decode base64"),
+            Message::InvalidPrefix => ("前缀编码失败", "Failed to encode the prefix"),
+            Message::SessionNotFound => ("会话不存在或已过期", "Session not found or expired"),
+            Message::NoImage => ("貌似还没有上传图片", "No image has been uploaded yet"),
+            Message::NoResult => ("貌似还没有推理结果", "No inference result yet"),
+            Message::JobNotFound => ("任务不存在或已结束", "Job not found or already finished"),
+            Message::LockFailed => ("数据锁定失败", "Failed to lock session data"),
+            Message::InferenceFailed => ("推理失败", "Inference failed"),
+            Message::TaskAborted => ("推理任务异常退出", "Inference task exited unexpectedly"),
+            Message::QueueFull => ("推理队列已满，请稍后重试", "Inference queue is full, please retry later"),
+            Message::ServiceStopped => ("推理服务已停止", "Inference service has stopped"),
+            Message::SerializationFailed => ("序列化失败", "Serialization failed"),
+        };
+        match locale {
+            Locale::ZhCn => zh,
+            Locale::En => en,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn negotiate(accept: &str) -> Locale {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_str(accept).unwrap());
+        Locale::negotiate(&headers, Locale::ZhCn)
+    }
+
+    #[test]
+    fn picks_highest_quality() {
+        assert_eq!(negotiate("zh-CN;q=0.5, en-US;q=0.8"), Locale::En);
+        assert_eq!(negotiate("en;q=0.3, zh"), Locale::ZhCn);
+    }
+
+    #[test]
+    fn keeps_client_order_for_equal_quality() {
+        assert_eq!(negotiate("en-GB, zh-TW"), Locale::En);
+        assert_eq!(negotiate("zh-TW;q=0.7, en;q=0.7"), Locale::ZhCn);
+    }
+
+    #[test]
+    fn skips_unsupported_and_rejected_languages() {
+        assert_eq!(negotiate("fr-FR, de;q=0.9, en;q=0.1"), Locale::En);
+        assert_eq!(negotiate("en;q=0, fr"), Locale::ZhCn);
+        assert_eq!(negotiate("fr, *;q=0.5, en;q=0.4"), Locale::ZhCn);
+    }
+
+    #[test]
+    fn falls_back_to_default() {
+        assert_eq!(Locale::negotiate(&HeaderMap::new(), Locale::En), Locale::En);
+        assert_eq!(negotiate("ja"), Locale::ZhCn);
+    }
+}
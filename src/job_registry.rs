@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use mixtex::CancelToken;
+
+/// 正在进行的推理任务，`/cancel/{job}` 通过任务 id 找到对应的取消标记
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, CancelToken>>,
+}
+
+impl JobRegistry {
+    /// 登记一个新任务，返回的凭证被丢弃时自动注销
+    pub fn register(self: &Arc<Self>) -> JobGuard {
+        let job_id = uuid::Uuid::new_v4().simple().to_string();
+        let cancel = CancelToken::default();
+        if let Ok(mut jobs) = self.jobs.lock() {
+            jobs.insert(job_id.clone(), cancel.clone());
+        }
+        JobGuard {
+            registry: Arc::clone(self),
+            job_id,
+            cancel,
+        }
+    }
+
+    /// 取消正在进行的任务，任务不存在或已结束时返回 false
+    pub fn cancel(&self, job_id: &str) -> bool {
+        let Ok(jobs) = self.jobs.lock() else {
+            return false;
+        };
+        match jobs.get(job_id) {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&self, job_id: &str) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            jobs.remove(job_id);
+        }
+    }
+}
+
+/// 任务的登记凭证。被丢弃时取消任务并将其移出登记表：
+/// 任务正常结束时取消没有影响，请求被中途丢弃（例如客户端断开）时推理随之停止
+pub struct JobGuard {
+    registry: Arc<JobRegistry>,
+    job_id: String,
+    cancel: CancelToken,
+}
+
+impl JobGuard {
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+        self.registry.remove(&self.job_id);
+    }
+}
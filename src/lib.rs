@@ -0,0 +1,36 @@
+//! MixTex 推理引擎：加载 ONNX 编码器 / 解码器与 tokenizer，预处理公式图片并解码出 LaTeX。
+//!
+//! HTTP 服务与命令行工具都建立在这个库之上，桌面端等其他程序也可以直接嵌入。
+//! 逐 token 生成的参数由 `GenerateOptions` 给出，beam search 使用 `BeamSearchConfig`：
+//!
+//! ```no_run
+//! use mixtex::{GenerateOptions, OrtInferenceSession, Sampler, SamplingConfig};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let onnx_session = OrtInferenceSession::new("./models", "./tokenizer/tokenizer.json", 0)?;
+//! let image = image::open("formula.png")?;
+//! let latex = onnx_session.recognize(image.clone(), GenerateOptions::default())?;
+//! println!("{}", latex);
+//!
+//! let options = GenerateOptions {
+//!     selector: Box::new(Sampler::new(SamplingConfig { seed: Some(42), ..SamplingConfig::default() })),
+//!     ..GenerateOptions::default()
+//! };
+//! for event in onnx_session.generate(image, options) {
+//!     println!("{:?}", event?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+mod onnx_inference_module;
+
+pub use onnx_inference_module::{
+    process_image_with_padding, check_repetition,
+    Preprocessor, PreprocessConfig, ImageSize, Resample, Alignment, ColorMode, PREPROCESSOR_CONFIG_FILE,
+    OrtInferenceSession, ImageInput, KvCache, EncoderOutput, TokenChoice,
+    BeamSearchConfig, BeamHypothesis, TokenSelector, GreedySelector, Sampler, SamplingConfig,
+    ConstrainedSelector, LatexConstraint,
+    CancelToken, GenerationOutput, StopReason, StoppingCriteria,
+    BatchConfig, BatchScheduler,
+    GenerateOptions, TokenEvent, TokenIter,
+};
@@ -1,26 +1,55 @@
 //src/main.rs
 #![windows_subsystem = "windows"]  // 放在最顶部
-mod onnx_inference_module;
-use onnx_inference_module::{process_image_with_padding, check_repetition};
 mod state;
+mod temporary_img;
+mod decode;
+mod session_store;
+mod job_registry;
+mod executor;
+mod config;
 mod handlers;
+mod cli;
+mod i18n;
 
-use axum::{Router, routing::{post, get}, http::Method};
-use std::sync::Arc;
+use axum::{Router, routing::{post, get}, http::{HeaderValue, Method}, extract::DefaultBodyLimit, middleware};
+use std::{sync::Arc, time::Duration};
 use state::AppStore;
-use handlers::{upload_image, stream_inference, final_decode, greet, bind_available_port};
-use tower_http::cors::{CorsLayer, Any}; // ✅ 导入 CORS
+use config::{AppConfig, Command};
+use handlers::{upload_image, stream_inference, final_decode, n_best, ocr, cancel_job, preprocess_preview, greet, bind_available_port, localize_errors};
+use tower_http::cors::{AllowOrigin, CorsLayer, Any}; // ✅ 导入 CORS
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let tokenizer_path = "./tokenizer/tokenizer.json";
-    let model_folder = "./models";
+fn main() -> anyhow::Result<()> {
+    // windows 子系统的程序没有控制台；带参数运行（`ocr` 子命令、`--help` 等）时连接到启动它的终端，
+    // 使结果、帮助与错误信息可见
+    #[cfg(windows)]
+    if std::env::args_os().len() > 1 {
+        attach_parent_console();
+    }
 
-    let app_store = Arc::new(AppStore::new(model_folder, tokenizer_path)?);
+    // 配置文件、环境变量与命令行参数
+    let (config, command) = AppConfig::load()?;
+    match command {
+        Some(Command::Ocr(args)) => cli::run_ocr(&config, args),
+        None => tokio::runtime::Runtime::new()?.block_on(serve(config)),
+    }
+}
 
-    // ✅ 添加 CORS 层，允许所有 origin/methods/headers
+async fn serve(config: AppConfig) -> anyhow::Result<()> {
+    let app_store = Arc::new(AppStore::new(&config)?);
+
+    // 定期清理过期会话
+    let sweeper_store = app_store.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            sweeper_store.sessions.prune_expired();
+        }
+    });
+
+    // ✅ 添加 CORS 层，允许配置中的 origin 与所有 headers
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(allowed_origins(&config.server.cors_origins)?)
         .allow_methods([Method::GET, Method::POST])
         .allow_headers(Any);
     
@@ -29,14 +58,46 @@ async fn main() -> anyhow::Result<()> {
         .route("/", get(greet))
         .route("/stream_inference", post(stream_inference))
         .route("/final_decode", post(final_decode))
+        .route("/n_best", post(n_best))
+        .route("/v1/ocr", post(ocr))
+        .route("/cancel/:job", post(cancel_job))
+        .route("/debug/preprocess", post(preprocess_preview))
         .with_state(app_store.clone())
+        .layer(middleware::from_fn_with_state(app_store.clone(), localize_errors))
+        .layer(DefaultBodyLimit::max(config.upload.max_body_bytes))
         .layer(cors); // ✅ 添加 CORS Layer
 
-    let (listener, addr) = bind_available_port(8000, 20).await?;
+    let server = &config.server;
+    let (listener, addr) = bind_available_port(server.host, server.port, server.port_tries).await?;
+    // 配置的端口被占用时会顺延到下一个可用端口，以实际绑定的地址为准
+    println!("Listening on http://{}", addr);
 
-    axum::serve(listener, app)
-        .await
-        .unwrap();
+    axum::serve(listener, app).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// 配置中包含 `*` 时允许任意来源
+fn allowed_origins(origins: &[String]) -> anyhow::Result<AllowOrigin> {
+    if origins.iter().any(|origin| origin == "*") {
+        return Ok(AllowOrigin::from(Any));
+    }
+    let origins = origins
+        .iter()
+        .map(|origin| HeaderValue::from_str(origin).map_err(|_| anyhow::anyhow!("Invalid CORS origin: {}", origin)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(AllowOrigin::list(origins))
+}
+
+/// 连接到父进程的控制台，从资源管理器启动（没有父控制台）时调用失败，直接忽略
+#[cfg(windows)]
+fn attach_parent_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
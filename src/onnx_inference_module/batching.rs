@@ -0,0 +1,321 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use ndarray::{concatenate, Axis};
+
+use super::encoder_output::EncoderOutput;
+use super::generation::{GenerationOutput, StopReason, StoppingCriteria};
+use super::kv_cache::KvCache;
+use super::logits::TokenChoice;
+use super::onnx_inference::{ImageInput, OrtInferenceSession};
+use super::sampling::TokenSelector;
+
+/// 跨请求批处理参数
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// 一个 batch 最多包含的请求数，模型导出为固定 batch 时取两者较小值
+    pub max_batch_size: usize,
+    /// 空闲时收到第一个请求后继续等待其他请求加入的时间
+    pub window: Duration,
+    /// 同时解码的 batch 数。解码器不支持填充时新请求无法加入进行中的 batch，
+    /// 多个 batch 并行可以避免新请求等待整个 batch 结束
+    pub concurrent_batches: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 8,
+            window: Duration::from_millis(10),
+            concurrent_batches: 2,
+        }
+    }
+}
+
+enum JobEvent {
+    Token(TokenChoice),
+    Finished(anyhow::Result<GenerationOutput>),
+}
+
+struct Job {
+    image: ImageInput,
+    selector: Box<dyn TokenSelector + Send>,
+    criteria: StoppingCriteria,
+    submitted: Instant,
+    events: Sender<JobEvent>,
+}
+
+/// batch 中仍在生成的一条序列
+struct Row {
+    selector: Box<dyn TokenSelector + Send>,
+    criteria: StoppingCriteria,
+    submitted: Instant,
+    events: Sender<JobEvent>,
+    token_ids: Vec<u32>,
+    first_token_time: Option<Duration>,
+    /// KV 缓存开头属于其他行的填充位置数
+    padding: usize,
+}
+
+impl Row {
+    /// 记录本步选出的 token，需要停止时返回原因。停止条件由 `StoppingCriteria::stop_reason` 判断，
+    /// 与 `generate` 一致，触发重复检测的 token 不会发给调用方。
+    fn push(&mut self, choice: TokenChoice, eos_token_id: u32) -> Option<StopReason> {
+        self.first_token_time.get_or_insert_with(|| self.submitted.elapsed());
+        self.token_ids.push(choice.token_id);
+        // 除 BOS 外已生成的 token 数
+        let generated = self.token_ids.len() - 1;
+        let stop_reason = self.criteria.stop_reason(&self.token_ids, generated, eos_token_id);
+        if stop_reason != Some(StopReason::Repetition) {
+            let _ = self.events.send(JobEvent::Token(choice));
+        }
+        stop_reason
+    }
+
+    fn finish(self, result: anyhow::Result<StopReason>) {
+        let output = result.map(|stop_reason| GenerationOutput {
+            first_token_time: self.first_token_time.unwrap_or_else(|| self.submitted.elapsed()),
+            total_time: self.submitted.elapsed(),
+            token_ids: self.token_ids,
+            stop_reason,
+        });
+        let _ = self.events.send(JobEvent::Finished(output));
+    }
+}
+
+/// 跨请求批处理调度器。贪心 / 采样请求合并成 batch：编码器一次处理同时加入的图片，
+/// 解码器对所有未结束的序列同步前进，某条序列结束后从 batch 中移除，不影响其余序列。
+/// 解码器导出了 `attention_mask` 与 `position_ids` 时，新请求在两步之间加入正在解码的 batch，
+/// KV 缓存在开头补零并在 attention mask 中屏蔽；否则新请求只能由空闲的 batch 循环处理。
+pub struct BatchScheduler {
+    jobs: Sender<Job>,
+}
+
+impl BatchScheduler {
+    pub fn spawn(onnx_session: Arc<OrtInferenceSession>, config: BatchConfig) -> anyhow::Result<Self> {
+        let max_batch_size = match onnx_session.max_batch_size() {
+            Some(fixed) => fixed.min(config.max_batch_size),
+            None => config.max_batch_size,
+        }
+        .max(1);
+        let (jobs, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..config.concurrent_batches.max(1) {
+            let onnx_session = Arc::clone(&onnx_session);
+            let receiver = Arc::clone(&receiver);
+            thread::Builder::new()
+                .name(format!("batch-scheduler-{}", index))
+                .spawn(move || run_scheduler(&onnx_session, &receiver, max_batch_size, config.window))?;
+        }
+        Ok(Self { jobs })
+    }
+
+    /// 提交一张图片并阻塞等待结果，每生成一个 token（含 EOS）调用一次 `on_token`
+    pub fn generate_with(
+        &self,
+        input_image: impl Into<ImageInput>,
+        selector: Box<dyn TokenSelector + Send>,
+        criteria: &StoppingCriteria,
+        mut on_token: impl FnMut(&TokenChoice),
+    ) -> anyhow::Result<GenerationOutput> {
+        let (events, receiver) = mpsc::channel();
+        let job = Job {
+            image: input_image.into(),
+            selector,
+            criteria: criteria.clone(),
+            submitted: Instant::now(),
+            events,
+        };
+        self.jobs
+            .send(job)
+            .map_err(|_| anyhow::anyhow!("Batch scheduler is not running"))?;
+
+        for event in receiver {
+            match event {
+                JobEvent::Token(choice) => on_token(&choice),
+                JobEvent::Finished(result) => return result,
+            }
+        }
+        anyhow::bail!("Batch scheduler dropped the request")
+    }
+}
+
+/// 一个 batch 循环。空闲时阻塞等待新请求，并在时间窗口内继续收集；
+/// 解码中只在拿得到队列锁时接收新请求，队列优先交给空闲的循环
+fn run_scheduler(onnx_session: &OrtInferenceSession, receiver: &Mutex<Receiver<Job>>, max_batch_size: usize, window: Duration) {
+    let mut batch: Option<Batch> = None;
+    loop {
+        let jobs = match &batch {
+            None => {
+                let Ok(receiver) = receiver.lock() else {
+                    return;
+                };
+                let Ok(first) = receiver.recv() else {
+                    return;
+                };
+                let deadline = Instant::now() + window;
+                let mut jobs = vec![first];
+                while jobs.len() < max_batch_size {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    match receiver.recv_timeout(remaining) {
+                        Ok(job) => jobs.push(job),
+                        Err(_) => break,
+                    }
+                }
+                jobs
+            }
+            Some(running) if onnx_session.supports_padding() && running.rows.len() < max_batch_size => {
+                let mut jobs = Vec::new();
+                if let Ok(receiver) = receiver.try_lock() {
+                    while running.rows.len() + jobs.len() < max_batch_size {
+                        match receiver.try_recv() {
+                            Ok(job) => jobs.push(job),
+                            Err(_) => break,
+                        }
+                    }
+                }
+                jobs
+            }
+            Some(_) => Vec::new(),
+        };
+
+        if !jobs.is_empty() {
+            batch = admit(onnx_session, batch.take(), jobs);
+        }
+        if let Some(mut running) = batch.take() {
+            match running.step(onnx_session) {
+                Ok(()) if !running.rows.is_empty() => batch = Some(running),
+                Ok(()) => {}
+                Err(e) => {
+                    for row in running.rows {
+                        row.finish(Err(anyhow::anyhow!("{:#}", e)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 正在解码的 batch，`rows`、KV 缓存与 encoder hidden states 的行一一对应
+struct Batch {
+    rows: Vec<Row>,
+    kv_cache: KvCache,
+    encoder_hidden_states: EncoderOutput,
+}
+
+/// 预处理并编码新请求的图片，加入 `batch`（为 None 时新建）。出错时只影响新加入的请求
+fn admit(onnx_session: &OrtInferenceSession, batch: Option<Batch>, jobs: Vec<Job>) -> Option<Batch> {
+    let bos_token_id = onnx_session.get_tokenizer().token_to_id("<s>").unwrap_or(0);
+
+    // 预处理失败只影响对应的请求
+    let mut rows = Vec::with_capacity(jobs.len());
+    let mut pixels = Vec::with_capacity(jobs.len());
+    for job in jobs {
+        let row = Row {
+            selector: job.selector,
+            criteria: job.criteria,
+            submitted: job.submitted,
+            events: job.events,
+            token_ids: vec![bos_token_id],
+            first_token_time: None,
+            padding: 0,
+        };
+        // 排队期间已被取消的请求不再参与推理
+        if row.criteria.is_cancelled() {
+            row.finish(Ok(StopReason::Cancelled));
+            continue;
+        }
+        match onnx_session.pixel_values(job.image) {
+            Ok(pixel_values) => {
+                pixels.push(pixel_values);
+                rows.push(row);
+            }
+            Err(e) => row.finish(Err(e)),
+        }
+    }
+    if rows.is_empty() {
+        return batch;
+    }
+
+    let joined = (|| -> anyhow::Result<Batch> {
+        // 图片预处理后尺寸一致，沿 batch 维拼接后一次编码
+        let views: Vec<_> = pixels.iter().map(|pixel_values| pixel_values.view()).collect();
+        let encoder_hidden_states = onnx_session.encode_pixels(concatenate(Axis(0), &views)?)?;
+        let kv_cache = onnx_session.empty_kv_cache(rows.len())?;
+        let Some(running) = &batch else {
+            return Ok(Batch { rows: Vec::new(), kv_cache, encoder_hidden_states });
+        };
+        // 新序列从 BOS 开始，past 长度为 0，在开头补零到与正在解码的序列相同的长度
+        let (kv_cache, _, padding) = running.kv_cache.append(&kv_cache)?;
+        rows.iter_mut().for_each(|row| row.padding = padding);
+        let encoder_hidden_states = running.encoder_hidden_states.append(&encoder_hidden_states)?;
+        Ok(Batch { rows: Vec::new(), kv_cache, encoder_hidden_states })
+    })();
+
+    match joined {
+        Ok(mut joined) => {
+            joined.rows = batch.map(|running| running.rows).unwrap_or_default();
+            joined.rows.append(&mut rows);
+            Some(joined)
+        }
+        Err(e) => {
+            for row in rows {
+                row.finish(Err(anyhow::anyhow!("{:#}", e)));
+            }
+            batch
+        }
+    }
+}
+
+impl Batch {
+    /// 所有序列前进一步，结束的序列从 batch 中移除；出错时 `rows` 中剩下的是尚未结束的序列
+    fn step(&mut self, onnx_session: &OrtInferenceSession) -> anyhow::Result<()> {
+        let eos_token_id = onnx_session.get_tokenizer().token_to_id("</s>").unwrap_or(30000);
+        let input_token_ids: Vec<u32> = self
+            .rows
+            .iter()
+            .map(|row| *row.token_ids.last().unwrap_or(&eos_token_id))
+            .collect();
+        let padding: Vec<usize> = self.rows.iter().map(|row| row.padding).collect();
+        let (logits, present_key_values) =
+            onnx_session.decoder_step_padded(&self.kv_cache, &input_token_ids, &self.encoder_hidden_states, &padding)?;
+
+        let mut keep = Vec::with_capacity(self.rows.len());
+        let mut running = Vec::with_capacity(self.rows.len());
+        for (index, (mut row, row_logits)) in self.rows.drain(..).zip(&logits).enumerate() {
+            // 被取消的序列直接移出 batch，不影响其余序列
+            if row.criteria.is_cancelled() {
+                row.finish(Ok(StopReason::Cancelled));
+                continue;
+            }
+            let token_id = row.selector.select(row_logits, &row.token_ids);
+            let choice = TokenChoice::from_logits(row_logits, token_id);
+            match row.push(choice, eos_token_id) {
+                Some(stop_reason) => row.finish(Ok(stop_reason)),
+                None => {
+                    keep.push(index);
+                    running.push(row);
+                }
+            }
+        }
+        self.rows = running;
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        if keep.len() == input_token_ids.len() {
+            self.kv_cache = present_key_values;
+            return Ok(());
+        }
+
+        // 去掉已结束序列对应的行，以及剩余各行共有的开头填充
+        self.kv_cache = present_key_values.reorder(&keep)?;
+        self.encoder_hidden_states = self.encoder_hidden_states.select(&keep)?;
+        let shared_padding = self.rows.iter().map(|row| row.padding).min().unwrap_or(0);
+        if shared_padding > 0 {
+            self.kv_cache.drop_front(shared_padding)?;
+            self.rows.iter_mut().for_each(|row| row.padding -= shared_padding);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use super::encoder_output::EncoderOutput;
+use super::generation::{StopReason, StoppingCriteria};
+use super::latex_constraint::LatexConstraint;
+use super::logits::{log_softmax, top_k_indices, TokenChoice};
+use super::onnx_inference::{ImageInput, OrtInferenceSession};
+
+/// Beam search 解码参数
+#[derive(Debug, Clone)]
+pub struct BeamSearchConfig {
+    /// 每一步保留的候选数量
+    pub beam_width: usize,
+    /// 长度惩罚指数，得分为 `log_prob / len^length_penalty`，大于 1 时偏好更长的结果
+    pub length_penalty: f32,
+    /// 为 true 时，只要已有 `beam_width` 个候选结束就停止搜索
+    pub early_stopping: bool,
+}
+
+impl Default for BeamSearchConfig {
+    fn default() -> Self {
+        Self {
+            beam_width: 4,
+            length_penalty: 1.0,
+            early_stopping: true,
+        }
+    }
+}
+
+/// 一条搜索结果，`token_ids` 以 BOS 开头并包含强制输入的前缀
+#[derive(Debug, Clone)]
+pub struct BeamHypothesis {
+    pub token_ids: Vec<u32>,
+    /// 除 BOS 外每个 token 的置信度
+    pub choices: Vec<TokenChoice>,
+    /// 累计 log 概率
+    pub log_prob: f32,
+    /// 经长度惩罚后的排序得分
+    pub score: f32,
+    /// 这条结果结束的原因
+    pub stop_reason: StopReason,
+}
+
+impl BeamHypothesis {
+    fn new(token_ids: Vec<u32>, choices: Vec<TokenChoice>, log_prob: f32, length_penalty: f32, stop_reason: StopReason) -> Self {
+        let score = normalized_score(log_prob, choices.len(), length_penalty);
+        Self { token_ids, choices, log_prob, score, stop_reason }
+    }
+}
+
+struct Beam {
+    token_ids: Vec<u32>,
+    choices: Vec<TokenChoice>,
+    log_prob: f32,
+    constraint: Option<LatexConstraint>,
+}
+
+/// 只按生成的 token 数计算长度惩罚，不计入 BOS 与强制输入的前缀
+fn normalized_score(log_prob: f32, generated_count: usize, length_penalty: f32) -> f32 {
+    let generated = generated_count.max(1) as f32;
+    log_prob / generated.powf(length_penalty)
+}
+
+impl OrtInferenceSession {
+    /// 对一张图片执行 beam search，返回按得分从高到低排列的最多 `beam_width` 条结果，每条结果带有各自的停止原因。
+    /// `prefix` 为强制输入的前缀 token，传入 `constraint` 时每条 beam 独立维护 LaTeX 结构状态。
+    /// 所有 beam 作为一个 batch 同时解码，每一步按选中候选的来源重排 KV 缓存。
+    pub fn beam_search(&self, input_image: impl Into<ImageInput>, config: &BeamSearchConfig, criteria: &StoppingCriteria, prefix: &[u32], mut constraint: Option<LatexConstraint>) -> anyhow::Result<Vec<BeamHypothesis>> {
+        let beam_width = config.beam_width.max(1);
+        let tokenizer = self.get_tokenizer();
+        let bos_token_id = tokenizer.token_to_id("<s>").unwrap_or(0);
+        let eos_token_id = tokenizer.token_to_id("</s>").unwrap_or(30000);
+
+        let encoder_hidden_states = self.encode_image(input_image)?;
+
+        // 前缀中除最后一个 token 外都先输入解码器，最后一个 token 作为第一步的输入
+        let mut token_ids = vec![bos_token_id];
+        token_ids.extend_from_slice(prefix);
+        let mut kv_cache = self.empty_kv_cache(1)?;
+        for &token_id in &token_ids[..token_ids.len() - 1] {
+            (_, kv_cache) = self.decoder_step(&kv_cache, token_id, &encoder_hidden_states)?;
+        }
+        if let Some(constraint) = constraint.as_mut() {
+            prefix.iter().for_each(|&token_id| constraint.advance(token_id));
+        }
+
+        let mut beams = vec![Beam {
+            token_ids,
+            choices: Vec::new(),
+            log_prob: 0.0,
+            constraint,
+        }];
+        let mut finished: Vec<BeamHypothesis> = Vec::new();
+        // 最近一步中因重复被丢弃的候选，所有 beam 都陷入重复时作为结果返回
+        let mut repetitive: Vec<BeamHypothesis> = Vec::new();
+        // 与 beam 一一对应的 encoder hidden states，各行内容相同，只在 beam 数量变化时重建
+        let mut beam_encoder_hidden_states = None;
+
+        for _step in 0..criteria.max_len {
+            if criteria.is_cancelled() {
+                break;
+            }
+            // 1. 每条 beam 前进一步，收集 2 * beam_width 个候选，保证去掉 EOS 后仍有足够的候选
+            let input_token_ids: Vec<u32> = beams
+                .iter()
+                .map(|beam| *beam.token_ids.last().unwrap_or(&bos_token_id))
+                .collect();
+            let (logits_rows, present_key_values) =
+                self.decoder_step_batch(&kv_cache, &input_token_ids, beam_encoder_hidden_states.as_ref().unwrap_or(&encoder_hidden_states))?;
+            let mut candidates: Vec<(usize, TokenChoice, f32)> = Vec::new();
+            for (beam_index, (beam, mut logits)) in beams.iter().zip(logits_rows).enumerate() {
+                if let Some(constraint) = &beam.constraint {
+                    constraint.mask_logits(&mut logits);
+                }
+                let log_probs = log_softmax(&logits);
+                for token_index in top_k_indices(&log_probs, 2 * beam_width) {
+                    let choice = TokenChoice::from_log_probs(&log_probs, token_index as u32);
+                    // 被约束屏蔽的 token
+                    if !choice.log_prob.is_finite() {
+                        continue;
+                    }
+                    candidates.push((beam_index, choice, beam.log_prob + choice.log_prob));
+                }
+            }
+            candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+            // 2. 选出下一轮的 beam，结束的候选进入结果
+            let mut next_beams = Vec::with_capacity(beam_width);
+            let mut parents = Vec::with_capacity(beam_width);
+            repetitive.clear();
+            for (rank, (beam_index, choice, log_prob)) in candidates.into_iter().enumerate() {
+                let mut token_ids = beams[beam_index].token_ids.clone();
+                token_ids.push(choice.token_id);
+                let mut choices = beams[beam_index].choices.clone();
+                choices.push(choice);
+
+                if choice.token_id == eos_token_id {
+                    if rank < beam_width {
+                        finished.push(BeamHypothesis::new(token_ids, choices, log_prob, config.length_penalty, StopReason::Eos));
+                    }
+                    continue;
+                }
+                // 与贪心解码一致，丢弃陷入重复的候选，保留其来源 beam（不含触发重复的 token）
+                if criteria.is_repetitive(&token_ids) {
+                    let beam = &beams[beam_index];
+                    // 同一条 beam 的多个候选只记录一次
+                    if rank < beam_width && !repetitive.iter().any(|h| h.token_ids == beam.token_ids) {
+                        repetitive.push(BeamHypothesis::new(
+                            beam.token_ids.clone(),
+                            beam.choices.clone(),
+                            beam.log_prob,
+                            config.length_penalty,
+                            StopReason::Repetition,
+                        ));
+                    }
+                    continue;
+                }
+                let mut constraint = beams[beam_index].constraint.clone();
+                if let Some(constraint) = constraint.as_mut() {
+                    constraint.advance(choice.token_id);
+                }
+                next_beams.push(Beam {
+                    token_ids,
+                    choices,
+                    log_prob,
+                    constraint,
+                });
+                parents.push(beam_index);
+                if next_beams.len() == beam_width {
+                    break;
+                }
+            }
+            beams = next_beams;
+
+            if beams.is_empty() || Self::beam_search_done(&beams, &mut finished, config, beam_width) {
+                break;
+            }
+            kv_cache = present_key_values.reorder(&parents)?;
+            let current_rows = beam_encoder_hidden_states.as_ref().map_or(1, EncoderOutput::batch_size);
+            if current_rows != parents.len() {
+                beam_encoder_hidden_states = Some(encoder_hidden_states.select(&vec![0; parents.len()])?);
+            }
+        }
+
+        // 结果不足时用仍在进行的 beam 补齐（达到 max_len 或被取消）；
+        // 没有 beam 剩下时说明所有 beam 都因重复被丢弃，用丢弃前的 beam 补齐
+        if finished.len() < beam_width {
+            if beams.is_empty() {
+                finished.append(&mut repetitive);
+            } else {
+                let stop_reason = if criteria.is_cancelled() { StopReason::Cancelled } else { StopReason::MaxLen };
+                for beam in beams {
+                    finished.push(BeamHypothesis::new(beam.token_ids, beam.choices, beam.log_prob, config.length_penalty, stop_reason));
+                }
+            }
+        }
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        finished.truncate(beam_width);
+        Ok(finished)
+    }
+
+    fn beam_search_done(beams: &[Beam], finished: &mut Vec<BeamHypothesis>, config: &BeamSearchConfig, beam_width: usize) -> bool {
+        if finished.len() < beam_width {
+            return false;
+        }
+        if config.early_stopping {
+            return true;
+        }
+        // 当前最好的 beam 也无法超过已结束结果中最差的一条时停止
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        finished.truncate(beam_width);
+        let worst_finished = finished.last().map(|h| h.score).unwrap_or(f32::NEG_INFINITY);
+        let best_running = beams
+            .iter()
+            .map(|beam| normalized_score(beam.log_prob, beam.choices.len(), config.length_penalty))
+            .fold(f32::NEG_INFINITY, f32::max);
+        best_running <= worst_finished
+    }
+}
@@ -0,0 +1,102 @@
+use image::{GrayImage, Luma};
+
+/// 用 Otsu 方法求全局阈值：使前景与背景两类的类间方差最大
+pub fn otsu_threshold(img: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let total_sum: f64 = histogram.iter().enumerate().map(|(value, &count)| value as f64 * count as f64).sum();
+
+    let (mut background_count, mut background_sum) = (0u64, 0f64);
+    let (mut best_threshold, mut best_variance) = (0u8, -1f64);
+    for (value, &count) in histogram.iter().enumerate() {
+        background_count += count;
+        background_sum += value as f64 * count as f64;
+        let foreground_count = total - background_count;
+        if background_count == 0 || foreground_count == 0 {
+            continue;
+        }
+        let background_mean = background_sum / background_count as f64;
+        let foreground_mean = (total_sum - background_sum) / foreground_count as f64;
+        let variance = background_count as f64 * foreground_count as f64 * (background_mean - foreground_mean).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_threshold = value as u8;
+        }
+    }
+    best_threshold
+}
+
+/// 全局阈值二值化，大于阈值的像素为白色。不判断哪一侧是前景，深色背景仍为黑色
+pub fn threshold(img: &GrayImage, threshold: u8) -> GrayImage {
+    GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        Luma([if img.get_pixel(x, y)[0] > threshold { 255 } else { 0 }])
+    })
+}
+
+/// 局部均值二值化：像素比周围 `(2 * radius + 1)²` 窗口的均值暗 `offset` 以上时为黑色，
+/// 适合光照或背景颜色不均匀的截图
+pub fn adaptive_threshold(img: &GrayImage, radius: u32, offset: f32) -> GrayImage {
+    let (width, height) = img.dimensions();
+    let (w, h) = (width as usize, height as usize);
+
+    // 积分图，比原图多一行一列
+    let mut integral = vec![0u64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row_sum = 0u64;
+        for x in 0..w {
+            row_sum += img.get_pixel(x as u32, y as u32)[0] as u64;
+            integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row_sum;
+        }
+    }
+
+    let radius = radius as usize;
+    GrayImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let (x0, y0) = (x.saturating_sub(radius), y.saturating_sub(radius));
+        let (x1, y1) = ((x + radius + 1).min(w), (y + radius + 1).min(h));
+        let sum = integral[y1 * (w + 1) + x1] + integral[y0 * (w + 1) + x0]
+            - integral[y0 * (w + 1) + x1]
+            - integral[y1 * (w + 1) + x0];
+        let mean = sum as f32 / ((x1 - x0) * (y1 - y0)) as f32;
+        let value = img.get_pixel(x as u32, y as u32)[0] as f32;
+        Luma([if value < mean - offset { 0 } else { 255 }])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn otsu_separates_two_levels() {
+        let img = GrayImage::from_fn(10, 10, |x, _| Luma([if x < 3 { 40 } else { 200 }]));
+        let level = otsu_threshold(&img);
+        assert!((40..200).contains(&level));
+        let binary = threshold(&img, level);
+        assert_eq!(binary.get_pixel(0, 0)[0], 0);
+        assert_eq!(binary.get_pixel(9, 9)[0], 255);
+    }
+
+    #[test]
+    fn otsu_on_uniform_image() {
+        let img = GrayImage::from_pixel(4, 4, Luma([128]));
+        assert_eq!(otsu_threshold(&img), 0);
+    }
+
+    #[test]
+    fn adaptive_follows_uneven_background() {
+        // 背景从左到右由暗变亮，每列中间有一个比背景暗 60 的点
+        let img = GrayImage::from_fn(32, 9, |x, y| {
+            let background = 100 + 4 * x as u8;
+            Luma([if y == 4 && x % 8 == 4 { background - 60 } else { background }])
+        });
+        let binary = adaptive_threshold(&img, 2, 10.0);
+        for (x, y, pixel) in binary.enumerate_pixels() {
+            let expected = if y == 4 && x % 8 == 4 { 0 } else { 255 };
+            assert_eq!(pixel[0], expected, "pixel ({}, {})", x, y);
+        }
+    }
+}
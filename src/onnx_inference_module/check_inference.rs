@@ -1,11 +1,12 @@
-pub fn check_repetition<T: PartialEq + Copy>(arr: &[T], repeats: usize) -> bool {
+/// 判断序列末尾是否由同一片段连续重复 `repeats` 次构成，只检查长度不小于 `min_pattern_length` 的片段
+pub fn check_repetition<T: PartialEq + Copy>(arr: &[T], repeats: usize, min_pattern_length: usize) -> bool {
     let length = arr.len();
-    if length < repeats {
+    if repeats < 2 || length < repeats {
         return false;
     }
 
     // 枚举所有可能的 pattern_length
-    for pattern_length in 1..=(length / repeats) {
+    for pattern_length in min_pattern_length.max(1)..=(length / repeats) {
         let total_len = pattern_length * repeats;
         if total_len > length {
             break;
@@ -29,4 +30,30 @@ pub fn check_repetition<T: PartialEq + Copy>(arr: &[T], repeats: usize) -> bool
         }
     }
     false
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_repeated_suffix() {
+        assert!(check_repetition(&[9, 1, 2, 1, 2, 1, 2], 3, 1));
+        assert!(check_repetition(&[5, 5, 5], 3, 1));
+        assert!(!check_repetition(&[1, 2, 1, 2, 3], 2, 1));
+    }
+
+    #[test]
+    fn ignores_patterns_shorter_than_minimum() {
+        // 单个 token 的重复（如连续的空格）不算重复
+        assert!(!check_repetition(&[7, 0, 0, 0, 0], 4, 2));
+        assert!(check_repetition(&[7, 1, 2, 1, 2, 1, 2, 1, 2], 4, 2));
+    }
+
+    #[test]
+    fn short_sequences_never_repeat() {
+        assert!(!check_repetition(&[1, 1], 3, 1));
+        assert!(!check_repetition::<u32>(&[], 2, 1));
+        assert!(!check_repetition(&[1, 1, 1], 1, 1));
+    }
+}
@@ -0,0 +1,292 @@
+use ort::session::Session;
+
+const INPUT_IDS: &str = "input_ids";
+const ENCODER_HIDDEN_STATES: &str = "encoder_hidden_states";
+const ATTENTION_MASK: &str = "attention_mask";
+const POSITION_IDS: &str = "position_ids";
+const LOGITS: &str = "logits";
+
+/// 从解码器 ONNX 模型的输入输出元数据中读出的结构信息。
+/// 输入输出都按名字绑定，past / present key/values 在对外接口中统一按
+/// `[layer0.key, layer0.value, layer1.key, ...]` 的顺序排列。
+#[derive(Debug, Clone)]
+pub struct DecoderSpec {
+    pub num_layers: usize,
+    pub num_heads: usize,
+    pub head_dim: usize,
+    /// 模型固定的 batch 大小，动态 batch 时为 None
+    pub batch_size: Option<usize>,
+    input_count: usize,
+    input_ids_index: usize,
+    encoder_hidden_states_index: usize,
+    /// 解码器自注意力的 `attention_mask`，形状为 (batch, past_seq_len + 1)，没有导出时为 None
+    attention_mask_index: Option<usize>,
+    /// `position_ids`，形状为 (batch, 1)，没有导出时为 None
+    position_ids_index: Option<usize>,
+    /// 每层 `past_key_values.N.key` / `.value` 在模型输入中的位置
+    past_input_indices: Vec<(usize, usize)>,
+    logits_index: usize,
+    /// 每层 `present.N.key` / `.value` 在模型输出中的位置
+    present_output_indices: Vec<(usize, usize)>,
+}
+
+impl DecoderSpec {
+    pub fn from_session(session: &Session) -> anyhow::Result<Self> {
+        let input_names: Vec<&str> = session.inputs.iter().map(|input| input.name.as_str()).collect();
+        let output_names: Vec<&str> = session.outputs.iter().map(|output| output.name.as_str()).collect();
+        let mut spec = Self::from_names(&input_names, &output_names)?;
+
+        // past 形状为 [batch, num_heads, past_seq_len, head_dim]
+        let past_key_input = &session.inputs[spec.past_input_indices[0].0];
+        // 动态维度在元数据中为 -1
+        let dimension = |axis: usize| -> anyhow::Result<usize> {
+            past_key_input
+                .input_type
+                .tensor_dimensions()
+                .and_then(|dims| dims.get(axis).copied())
+                .filter(|&dim| dim > 0)
+                .map(|dim| dim as usize)
+                .ok_or_else(|| anyhow::anyhow!("Input {} has no static dimension {}", past_key_input.name, axis))
+        };
+        spec.num_heads = dimension(1)?;
+        spec.head_dim = dimension(3)?;
+        spec.batch_size = dimension(0).ok();
+        Ok(spec)
+    }
+
+    /// 只根据输入输出的名字确定各输入输出的位置，`num_heads`、`head_dim` 与 `batch_size` 由调用方从张量形状中补全
+    fn from_names(input_names: &[&str], output_names: &[&str]) -> anyhow::Result<Self> {
+        let input_ids_index = find_index(input_names, INPUT_IDS)?;
+        let encoder_hidden_states_index = find_index(input_names, ENCODER_HIDDEN_STATES)?;
+        let attention_mask_index = find_index(input_names, ATTENTION_MASK).ok();
+        let position_ids_index = find_index(input_names, POSITION_IDS).ok();
+        let past_input_indices = collect_layers(input_names, "past_key_values")?;
+        if past_input_indices.is_empty() {
+            anyhow::bail!("Decoder model has no past_key_values.N.key/value inputs: {:?}", input_names);
+        }
+        let num_layers = past_input_indices.len();
+
+        // 没有按名字导出 present 时，退回到 logits 之后按层顺序排列的约定
+        let logits_index = find_index(output_names, LOGITS).unwrap_or(0);
+        let mut present_output_indices = collect_layers(output_names, "present")?;
+        if present_output_indices.is_empty() {
+            let rest: Vec<usize> = (0..output_names.len()).filter(|&i| i != logits_index).collect();
+            if rest.len() != num_layers * 2 {
+                anyhow::bail!("Cannot map decoder outputs {:?} to {} layers", output_names, num_layers);
+            }
+            present_output_indices = rest.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+        }
+        if present_output_indices.len() != num_layers {
+            anyhow::bail!(
+                "Decoder has {} past inputs but {} present outputs",
+                num_layers,
+                present_output_indices.len()
+            );
+        }
+
+        Ok(Self {
+            num_layers,
+            num_heads: 0,
+            head_dim: 0,
+            batch_size: None,
+            input_count: input_names.len(),
+            input_ids_index,
+            encoder_hidden_states_index,
+            attention_mask_index,
+            position_ids_index,
+            past_input_indices,
+            logits_index,
+            present_output_indices,
+        })
+    }
+
+    /// 同时导出了 `attention_mask` 与 `position_ids` 时，batch 中各行的 past 可以在开头填充不同的长度，
+    /// 新序列可以加入正在解码的 batch
+    pub fn supports_padding(&self) -> bool {
+        self.attention_mask_index.is_some() && self.position_ids_index.is_some()
+    }
+
+    /// 按模型声明的输入顺序排列解码器输入，`past` 为按层排列的 key/value。
+    /// `attention_mask` 与 `position_ids` 只在模型导出了对应输入时使用，否则被忽略
+    pub fn bind_inputs<V>(&self, input_ids: V, encoder_hidden_states: V, attention_mask: V, position_ids: V, past: Vec<V>) -> anyhow::Result<Vec<V>> {
+        if past.len() != self.num_layers * 2 {
+            anyhow::bail!("Expected {} past tensors, got {}", self.num_layers * 2, past.len());
+        }
+        let mut slots: Vec<Option<V>> = (0..self.input_count).map(|_| None).collect();
+        slots[self.input_ids_index] = Some(input_ids);
+        slots[self.encoder_hidden_states_index] = Some(encoder_hidden_states);
+        if let Some(index) = self.attention_mask_index {
+            slots[index] = Some(attention_mask);
+        }
+        if let Some(index) = self.position_ids_index {
+            slots[index] = Some(position_ids);
+        }
+        let mut past = past.into_iter();
+        for &(key_index, value_index) in &self.past_input_indices {
+            slots[key_index] = past.next();
+            slots[value_index] = past.next();
+        }
+        slots
+            .into_iter()
+            .enumerate()
+            .map(|(i, slot)| slot.ok_or_else(|| anyhow::anyhow!("Decoder input #{} is not bound", i)))
+            .collect()
+    }
+
+    /// 拆分解码器输出为 logits 与按层排列的 present key/values
+    pub fn split_outputs<V>(&self, outputs: Vec<V>) -> anyhow::Result<(V, Vec<V>)> {
+        let mut slots: Vec<Option<V>> = outputs.into_iter().map(Some).collect();
+        let mut take = |index: usize| -> anyhow::Result<V> {
+            slots
+                .get_mut(index)
+                .and_then(Option::take)
+                .ok_or_else(|| anyhow::anyhow!("Decoder output #{} is missing", index))
+        };
+        let logits = take(self.logits_index)?;
+        let mut present = Vec::with_capacity(self.num_layers * 2);
+        for &(key_index, value_index) in &self.present_output_indices {
+            present.push(take(key_index)?);
+            present.push(take(value_index)?);
+        }
+        Ok((logits, present))
+    }
+}
+
+fn find_index(names: &[&str], name: &str) -> anyhow::Result<usize> {
+    names
+        .iter()
+        .position(|&n| n == name)
+        .ok_or_else(|| anyhow::anyhow!("Decoder model has no `{}` among {:?}", name, names))
+}
+
+/// 收集形如 `{prefix}.N.key` / `{prefix}.N.value` 的名字，按层号返回 (key, value) 的位置
+fn collect_layers(names: &[&str], prefix: &str) -> anyhow::Result<Vec<(usize, usize)>> {
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    for (index, name) in names.iter().enumerate() {
+        let Some(rest) = name.strip_prefix(prefix).and_then(|rest| rest.strip_prefix('.')) else {
+            continue;
+        };
+        let Some((layer, kind)) = rest.split_once('.') else {
+            continue;
+        };
+        let Ok(layer) = layer.parse::<usize>() else {
+            continue;
+        };
+        match kind {
+            "key" => keys.push((layer, index)),
+            "value" => values.push((layer, index)),
+            _ => {}
+        }
+    }
+    keys.sort_unstable();
+    values.sort_unstable();
+
+    let mut layers = Vec::with_capacity(keys.len());
+    for (expected, (&(key_layer, key_index), &(value_layer, value_index))) in keys.iter().zip(values.iter()).enumerate() {
+        if key_layer != expected || value_layer != expected {
+            anyhow::bail!("`{}` layers are not contiguous from 0: {:?}", prefix, names);
+        }
+        layers.push((key_index, value_index));
+    }
+    if keys.len() != values.len() {
+        anyhow::bail!("`{}` has {} keys but {} values", prefix, keys.len(), values.len());
+    }
+    Ok(layers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind(spec: &DecoderSpec) -> anyhow::Result<Vec<&'static str>> {
+        spec.bind_inputs("ids", "enc", "mask", "pos", vec!["k0", "v0", "k1", "v1"])
+    }
+
+    #[test]
+    fn collects_layers_by_index() {
+        let names = [
+            "past_key_values.1.key",
+            "input_ids",
+            "past_key_values.0.value",
+            "past_key_values.1.value",
+            "past_key_values.0.key",
+            "past_key_values.0.bias",
+            "past_key_values.x.key",
+        ];
+        assert_eq!(collect_layers(&names, "past_key_values").unwrap(), [(4, 2), (0, 3)]);
+        assert!(collect_layers(&names, "present").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_missing_layers() {
+        let gap = ["present.0.key", "present.0.value", "present.2.key", "present.2.value"];
+        assert!(collect_layers(&gap, "present").is_err());
+        let missing_value = ["present.0.key", "present.0.value", "present.1.key"];
+        assert!(collect_layers(&missing_value, "present").is_err());
+        let duplicate = ["present.0.key", "present.0.key", "present.0.value"];
+        assert!(collect_layers(&duplicate, "present").is_err());
+    }
+
+    #[test]
+    fn binds_inputs_in_model_order() {
+        let inputs = [
+            "past_key_values.1.value",
+            "encoder_hidden_states",
+            "past_key_values.0.key",
+            "input_ids",
+            "past_key_values.1.key",
+            "past_key_values.0.value",
+        ];
+        // 只有 logits，没有可以对应到两层的 present 输出
+        let error = DecoderSpec::from_names(&inputs, &["logits"]).unwrap_err();
+        assert!(error.to_string().contains("2 layers"));
+
+        let outputs = ["logits", "present.0.key", "present.0.value", "present.1.key", "present.1.value"];
+        let spec = DecoderSpec::from_names(&inputs, &outputs).unwrap();
+        assert_eq!(spec.num_layers, 2);
+        assert!(!spec.supports_padding());
+        assert_eq!(bind(&spec).unwrap(), ["v1", "enc", "k0", "ids", "k1", "v0"]);
+        assert!(spec.bind_inputs("ids", "enc", "mask", "pos", vec!["k0", "v0"]).is_err());
+    }
+
+    #[test]
+    fn binds_attention_mask_and_position_ids() {
+        let inputs = [
+            "input_ids",
+            "attention_mask",
+            "encoder_hidden_states",
+            "past_key_values.0.key",
+            "past_key_values.0.value",
+            "past_key_values.1.key",
+            "past_key_values.1.value",
+            "position_ids",
+        ];
+        let outputs = ["logits", "present.0.key", "present.0.value", "present.1.key", "present.1.value"];
+        let spec = DecoderSpec::from_names(&inputs, &outputs).unwrap();
+        assert!(spec.supports_padding());
+        assert_eq!(bind(&spec).unwrap(), ["ids", "mask", "enc", "k0", "v0", "k1", "v1", "pos"]);
+    }
+
+    #[test]
+    fn splits_named_outputs() {
+        let inputs = ["input_ids", "encoder_hidden_states", "past_key_values.0.key", "past_key_values.0.value", "past_key_values.1.key", "past_key_values.1.value"];
+        let outputs = ["present.1.key", "present.0.value", "logits", "present.1.value", "present.0.key"];
+        let spec = DecoderSpec::from_names(&inputs, &outputs).unwrap();
+        let (logits, present) = spec.split_outputs(outputs.to_vec()).unwrap();
+        assert_eq!(logits, "logits");
+        assert_eq!(present, ["present.0.key", "present.0.value", "present.1.key", "present.1.value"]);
+        assert!(spec.split_outputs(outputs[..4].to_vec()).is_err());
+    }
+
+    #[test]
+    fn splits_unnamed_outputs_after_logits() {
+        let inputs = ["input_ids", "encoder_hidden_states", "past_key_values.0.key", "past_key_values.0.value"];
+        let spec = DecoderSpec::from_names(&inputs, &["out0", "out1", "out2"]).unwrap();
+        let (logits, present) = spec.split_outputs(vec!["out0", "out1", "out2"]).unwrap();
+        assert_eq!((logits, present), ("out0", vec!["out1", "out2"]));
+
+        assert!(DecoderSpec::from_names(&inputs, &["logits", "out1"]).is_err());
+        assert!(DecoderSpec::from_names(&inputs[1..], &["logits", "out1", "out2"]).is_err());
+    }
+}
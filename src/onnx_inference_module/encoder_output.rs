@@ -0,0 +1,36 @@
+use ndarray::{concatenate, Axis};
+use ort::session::SessionInputValue;
+use ort::value::{DynValue, Tensor};
+
+/// 编码器输出的 hidden states，形状为 (batch, seq_len, hidden)。
+/// ONNX Runtime 张量每张图片只创建一次，之后的每一步解码都以视图方式传入，不再复制整块 cross-attention 数据。
+pub struct EncoderOutput {
+    tensor: Tensor<f32>,
+}
+
+impl EncoderOutput {
+    pub(super) fn from_value(value: DynValue) -> anyhow::Result<Self> {
+        Ok(Self { tensor: value.downcast()? })
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.tensor.extract_tensor().len_of(Axis(0))
+    }
+
+    /// 作为解码器输入的借用视图
+    pub(super) fn as_input(&self) -> SessionInputValue<'_> {
+        SessionInputValue::from(self.tensor.view())
+    }
+
+    /// 按 `indices` 重新排列 batch 维并创建新的张量，只在 batch 的组成发生变化时调用
+    pub fn select(&self, indices: &[usize]) -> anyhow::Result<Self> {
+        let selected = self.tensor.extract_tensor().select(Axis(0), indices);
+        Ok(Self { tensor: Tensor::from_array(selected)? })
+    }
+
+    /// 沿 batch 维把 `other` 的行接在后面，用于新请求加入正在解码的 batch
+    pub fn append(&self, other: &EncoderOutput) -> anyhow::Result<Self> {
+        let joined = concatenate(Axis(0), &[self.tensor.extract_tensor(), other.tensor.extract_tensor()])?;
+        Ok(Self { tensor: Tensor::from_array(joined)? })
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use serde::Serialize;
+
+use super::check_inference::check_repetition;
+use super::logits::TokenChoice;
+use super::onnx_inference::{ImageInput, OrtInferenceSession};
+use super::token_stream::{GenerateOptions, TokenEvent};
+
+/// 生成结束的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// 生成了 EOS
+    Eos,
+    /// 达到最大长度被截断
+    MaxLen,
+    /// 检测到重复输出而停止
+    Repetition,
+    /// 客户端断开或主动取消
+    Cancelled,
+}
+
+/// 跨线程共享的取消标记，解码循环每一步检查一次
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// 停止生成的条件
+#[derive(Debug, Clone)]
+pub struct StoppingCriteria {
+    /// 最多生成的 token 数（不含 BOS 与前缀）
+    pub max_len: usize,
+    /// 末尾同一片段连续出现多少次视为重复，小于 2 时关闭重复检测
+    pub repeat_count: usize,
+    /// 参与重复检测的最短片段长度
+    pub min_pattern_length: usize,
+    /// 被取消后在下一步停止
+    pub cancel: CancelToken,
+}
+
+impl Default for StoppingCriteria {
+    fn default() -> Self {
+        Self {
+            max_len: 512,
+            repeat_count: 10,
+            min_pattern_length: 1,
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+impl StoppingCriteria {
+    /// 把新选出的 token 加入 `token_ids` 之后调用，判断是否停止生成；`generated` 为包括该 token 在内已生成的 token 数。
+    /// 返回 `Repetition` 时该 token 不再交给调用方，其余原因下该 token 照常返回后停止。
+    /// 逐条生成（`TokenIter`）与批处理调度器共用这一判断。
+    pub fn stop_reason(&self, token_ids: &[u32], generated: usize, eos_token_id: u32) -> Option<StopReason> {
+        if self.is_repetitive(token_ids) {
+            return Some(StopReason::Repetition);
+        }
+        if token_ids.last() == Some(&eos_token_id) {
+            return Some(StopReason::Eos);
+        }
+        if generated >= self.max_len {
+            return Some(StopReason::MaxLen);
+        }
+        None
+    }
+
+    pub fn is_repetitive(&self, token_ids: &[u32]) -> bool {
+        check_repetition(token_ids, self.repeat_count, self.min_pattern_length)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+}
+
+/// 一次完整生成的结果
+#[derive(Debug, Clone)]
+pub struct GenerationOutput {
+    /// 以 BOS 开头，包含前缀与生成的全部 token
+    pub token_ids: Vec<u32>,
+    pub stop_reason: StopReason,
+    /// 从开始到得到第一个生成 token 的耗时（含预处理与编码器）
+    pub first_token_time: Duration,
+    pub total_time: Duration,
+}
+
+impl OrtInferenceSession {
+    /// 逐 token 生成，每选出一个 token（含 EOS）调用一次 `on_token`
+    pub fn generate_with(
+        &self,
+        input_image: impl Into<ImageInput>,
+        options: GenerateOptions,
+        mut on_token: impl FnMut(&TokenChoice),
+    ) -> anyhow::Result<GenerationOutput> {
+        for event in self.generate(input_image, options) {
+            match event? {
+                TokenEvent::Token(choice) => on_token(&choice),
+                TokenEvent::Done(output) => return Ok(output),
+            }
+        }
+        anyhow::bail!("Generation ended without a result")
+    }
+
+    /// 识别一张图片并返回 LaTeX 文本
+    pub fn recognize(&self, input_image: impl Into<ImageInput>, options: GenerateOptions) -> anyhow::Result<String> {
+        let output = self.generate_with(input_image, options, |_| {})?;
+        self.decode_tokens(&output.token_ids)
+    }
+}
@@ -0,0 +1,201 @@
+use ndarray::{concatenate, ArrayD, Axis, IxDyn, Slice};
+use ort::session::SessionInputValue;
+use ort::value::{DynValue, Tensor};
+
+use super::decoder_spec::DecoderSpec;
+
+/// 解码器的 past key/values，直接持有 ONNX Runtime 输出的张量：每一步的 present 以视图方式作为下一步的 past 传入，
+/// 不在 ndarray 与 ONNX Runtime 之间来回复制；只有 `try_clone` / `reorder` / `truncate` 等会创建新的张量。
+/// 张量按 `[layer0.key, layer0.value, layer1.key, ...]` 排列，
+/// 形状均为 (batch, num_heads, seq_len, head_dim)。
+pub struct KvCache {
+    tensors: Vec<Tensor<f32>>,
+}
+
+impl KvCache {
+    /// 长度为 0 的初始缓存
+    pub fn empty(spec: &DecoderSpec, batch_size: usize) -> anyhow::Result<Self> {
+        let tensors = (0..spec.num_layers * 2)
+            .map(|_| Tensor::from_array(ArrayD::<f32>::zeros(IxDyn(&[batch_size, spec.num_heads, 0, spec.head_dim]))))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { tensors })
+    }
+
+    pub(super) fn from_values(values: Vec<DynValue>) -> anyhow::Result<Self> {
+        let tensors = values
+            .into_iter()
+            .map(|value| value.downcast())
+            .collect::<Result<_, _>>()?;
+        Ok(Self { tensors })
+    }
+
+    /// 深拷贝所有张量。ONNX Runtime 的张量不能直接 `Clone`，复制需要重新分配，因此可能失败
+    pub fn try_clone(&self) -> anyhow::Result<Self> {
+        let tensors = self
+            .tensors
+            .iter()
+            .map(|tensor| Tensor::from_array(tensor.extract_tensor().to_owned()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { tensors })
+    }
+
+    /// 作为解码器 past 输入的借用视图
+    pub(super) fn as_inputs(&self) -> Vec<SessionInputValue<'_>> {
+        self.tensors
+            .iter()
+            .map(|tensor| SessionInputValue::from(tensor.view()))
+            .collect()
+    }
+
+    /// 已缓存的 token 数
+    pub fn seq_len(&self) -> usize {
+        self.tensors.first().map(|t| t.extract_tensor().len_of(Axis(2))).unwrap_or(0)
+    }
+
+    /// 只保留前 `len` 个位置，用于回退到更早的解码状态
+    pub fn truncate(&mut self, len: usize) -> anyhow::Result<()> {
+        if len >= self.seq_len() {
+            return Ok(());
+        }
+        for tensor in self.tensors.iter_mut() {
+            let truncated = tensor.extract_tensor().slice_axis(Axis(2), Slice::from(..len)).to_owned();
+            *tensor = Tensor::from_array(truncated)?;
+        }
+        Ok(())
+    }
+
+    /// 按 `indices` 重新排列 batch 维，第 i 行取自原来的第 `indices[i]` 行。
+    /// 索引可以重复（beam search 中多个候选来自同一条 beam），也可以缺省（移除已结束的序列）。
+    pub fn reorder(&self, indices: &[usize]) -> anyhow::Result<Self> {
+        let tensors = self
+            .tensors
+            .iter()
+            .map(|tensor| Tensor::from_array(tensor.extract_tensor().select(Axis(0), indices)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { tensors })
+    }
+
+    /// 去掉开头的 `len` 个位置，用于移除 batch 中所有行共有的填充
+    pub fn drop_front(&mut self, len: usize) -> anyhow::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        for tensor in self.tensors.iter_mut() {
+            let trimmed = tensor.extract_tensor().slice_axis(Axis(2), Slice::from(len..)).to_owned();
+            *tensor = Tensor::from_array(trimmed)?;
+        }
+        Ok(())
+    }
+
+    /// 沿 batch 维把 `other` 的行接在后面。较短的一方在开头补零到相同长度，
+    /// 补上的位置需要由调用方在 attention mask 中屏蔽；返回 (本缓存各行, `other` 各行) 补上的位置数
+    pub fn append(&self, other: &KvCache) -> anyhow::Result<(Self, usize, usize)> {
+        let (self_len, other_len) = (self.seq_len(), other.seq_len());
+        let seq_len = self_len.max(other_len);
+        let tensors = self
+            .tensors
+            .iter()
+            .zip(&other.tensors)
+            .map(|(a, b)| {
+                let (a, b) = (pad_front(a, seq_len)?, pad_front(b, seq_len)?);
+                Ok(Tensor::from_array(concatenate(Axis(0), &[a.view(), b.view()])?)?)
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok((Self { tensors }, seq_len - self_len, seq_len - other_len))
+    }
+}
+
+/// 在 seq_len 维开头补零到 `seq_len`
+fn pad_front(tensor: &Tensor<f32>, seq_len: usize) -> anyhow::Result<ArrayD<f32>> {
+    let view = tensor.extract_tensor();
+    let mut shape = view.shape().to_vec();
+    let pad = seq_len - shape[2];
+    if pad == 0 {
+        return Ok(view.to_owned());
+    }
+    shape[2] = pad;
+    Ok(concatenate(Axis(2), &[ArrayD::<f32>::zeros(IxDyn(&shape)).view(), view])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 一层（key 与 value 两个张量）、每个头 1 维的缓存，行 `r` 位置 `p` 的值为 `r * 100 + p + 1`，value 取负
+    fn cache(batch_size: usize, seq_len: usize) -> KvCache {
+        let key = ArrayD::from_shape_fn(IxDyn(&[batch_size, 2, seq_len, 1]), |index| (index[0] * 100 + index[2] + 1) as f32);
+        let value = key.mapv(|x| -x);
+        KvCache { tensors: vec![Tensor::from_array(key).unwrap(), Tensor::from_array(value).unwrap()] }
+    }
+
+    fn shape(cache: &KvCache) -> Vec<usize> {
+        cache.tensors[0].extract_tensor().shape().to_vec()
+    }
+
+    /// key 张量中第 0 个头各行各位置的值
+    fn rows(cache: &KvCache) -> Vec<Vec<f32>> {
+        let key = cache.tensors[0].extract_tensor();
+        key.outer_iter()
+            .map(|row| row.index_axis(Axis(0), 0).iter().copied().collect())
+            .collect()
+    }
+
+    #[test]
+    fn try_clone_is_a_deep_copy() {
+        let mut original = cache(2, 3);
+        let copy = original.try_clone().unwrap();
+        original.truncate(1).unwrap();
+        assert_eq!(shape(&copy), [2, 2, 3, 1]);
+        assert_eq!(rows(&copy), rows(&cache(2, 3)));
+    }
+
+    #[test]
+    fn truncate_keeps_leading_positions() {
+        let mut cache = cache(2, 4);
+        cache.truncate(2).unwrap();
+        assert_eq!(cache.seq_len(), 2);
+        assert_eq!(rows(&cache), [vec![1.0, 2.0], vec![101.0, 102.0]]);
+        cache.truncate(5).unwrap();
+        assert_eq!(cache.seq_len(), 2);
+    }
+
+    #[test]
+    fn reorder_repeats_and_omits_rows() {
+        let reordered = cache(3, 2).reorder(&[2, 0, 0]).unwrap();
+        assert_eq!(shape(&reordered), [3, 2, 2, 1]);
+        assert_eq!(rows(&reordered), [vec![201.0, 202.0], vec![1.0, 2.0], vec![1.0, 2.0]]);
+
+        let reordered = cache(3, 2).reorder(&[1]).unwrap();
+        assert_eq!(rows(&reordered), [vec![101.0, 102.0]]);
+        // value 张量按相同的索引重排
+        assert_eq!(reordered.tensors[1].extract_tensor()[[0, 1, 1, 0]], -102.0);
+    }
+
+    #[test]
+    fn drop_front_removes_leading_positions() {
+        let mut cache = cache(2, 3);
+        cache.drop_front(2).unwrap();
+        assert_eq!(shape(&cache), [2, 2, 1, 1]);
+        assert_eq!(rows(&cache), [vec![3.0], vec![103.0]]);
+    }
+
+    #[test]
+    fn append_pads_shorter_cache_at_front() {
+        let (joined, pad_self, pad_other) = cache(1, 3).append(&cache(2, 1)).unwrap();
+        assert_eq!((pad_self, pad_other), (0, 2));
+        assert_eq!(shape(&joined), [3, 2, 3, 1]);
+        assert_eq!(rows(&joined), [vec![1.0, 2.0, 3.0], vec![0.0, 0.0, 1.0], vec![0.0, 0.0, 101.0]]);
+
+        let (joined, pad_self, pad_other) = cache(1, 0).append(&cache(1, 2)).unwrap();
+        assert_eq!((pad_self, pad_other), (2, 0));
+        assert_eq!(rows(&joined), [vec![0.0, 0.0], vec![1.0, 2.0]]);
+    }
+
+    #[test]
+    fn pad_front_fills_zeros() {
+        let cache = cache(1, 1);
+        let padded = pad_front(&cache.tensors[1], 3).unwrap();
+        assert_eq!(padded.shape(), [1, 2, 3, 1]);
+        assert_eq!(padded.index_axis(Axis(1), 0).iter().copied().collect::<Vec<_>>(), [0.0, 0.0, -1.0]);
+    }
+}
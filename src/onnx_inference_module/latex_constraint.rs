@@ -0,0 +1,326 @@
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
+use super::sampling::TokenSelector;
+
+/// 尚未闭合的结构
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Group {
+    Brace,
+    Left,
+    Env(String),
+}
+
+/// 跨 token 保存的词法状态，命令名可能被切分到多个 token 中
+#[derive(Debug, Clone, Default)]
+enum Lexer {
+    #[default]
+    Text,
+    /// 刚读到 `\`
+    Escape,
+    /// 正在读取命令名，直到遇到非字母字符
+    Command(String),
+    /// `\begin` / `\end` 之后的 `{name}`
+    EnvName { begin: bool, name: String, opened: bool },
+}
+
+/// LaTeX 结构状态：花括号深度、`\left`/`\right` 配对与环境嵌套
+#[derive(Debug, Clone, Default)]
+struct LatexState {
+    stack: Vec<Group>,
+    lexer: Lexer,
+}
+
+struct Invalid;
+
+impl LatexState {
+    fn feed_str(&mut self, text: &str) -> Result<(), Invalid> {
+        text.chars().try_for_each(|c| self.feed(c))
+    }
+
+    fn feed(&mut self, c: char) -> Result<(), Invalid> {
+        match std::mem::take(&mut self.lexer) {
+            Lexer::Text => match c {
+                '\\' => self.lexer = Lexer::Escape,
+                '{' => self.stack.push(Group::Brace),
+                '}' => self.pop(Group::Brace)?,
+                _ => {}
+            },
+            Lexer::Escape => {
+                // `\{`、`\}`、`\\` 等控制符号按普通文本处理
+                if c.is_ascii_alphabetic() {
+                    self.lexer = Lexer::Command(c.to_string());
+                }
+            }
+            Lexer::Command(mut name) => {
+                if c.is_ascii_alphabetic() {
+                    name.push(c);
+                    self.lexer = Lexer::Command(name);
+                } else {
+                    self.finish_command(&name)?;
+                    self.feed(c)?;
+                }
+            }
+            Lexer::EnvName { begin, mut name, opened } => {
+                if !opened {
+                    if is_whitespace(c) {
+                        self.lexer = Lexer::EnvName { begin, name, opened };
+                    } else if c == '{' {
+                        self.lexer = Lexer::EnvName { begin, name, opened: true };
+                    } else {
+                        return Err(Invalid);
+                    }
+                } else if c == '}' {
+                    if begin {
+                        self.stack.push(Group::Env(name));
+                    } else {
+                        self.pop(Group::Env(name))?;
+                    }
+                } else if c.is_ascii_alphabetic() || c == '*' {
+                    name.push(c);
+                    self.lexer = Lexer::EnvName { begin, name, opened };
+                } else {
+                    return Err(Invalid);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_command(&mut self, name: &str) -> Result<(), Invalid> {
+        match name {
+            "left" => self.stack.push(Group::Left),
+            "right" => self.pop(Group::Left)?,
+            "begin" | "end" => {
+                self.lexer = Lexer::EnvName { begin: name == "begin", name: String::new(), opened: false };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self, expected: Group) -> Result<(), Invalid> {
+        match self.stack.pop() {
+            Some(group) if group == expected => Ok(()),
+            _ => Err(Invalid),
+        }
+    }
+
+    /// 当前位置是否可以结束输出
+    fn can_finish(&self) -> bool {
+        let mut state = self.clone();
+        match std::mem::take(&mut state.lexer) {
+            Lexer::Text => {}
+            Lexer::Command(name) => {
+                if state.finish_command(&name).is_err() || !matches!(state.lexer, Lexer::Text) {
+                    return false;
+                }
+            }
+            Lexer::Escape | Lexer::EnvName { .. } => return false,
+        }
+        state.stack.is_empty()
+    }
+
+    fn is_pending(&self) -> bool {
+        !matches!(self.lexer, Lexer::Text)
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    // 字节级 BPE 中 `Ġ`、`Ċ`、`ĉ` 分别表示空格、换行与制表符
+    c.is_whitespace() || matches!(c, 'Ġ' | 'Ċ' | 'ĉ')
+}
+
+/// 词表中每个 token 的原始文本，加载模型时构建一次
+pub struct LatexGrammar {
+    token_texts: Vec<String>,
+    /// token 是否包含 `\`、`{`、`}`，不包含的 token 在没有未完成命令时总是合法的
+    structural: Vec<bool>,
+    eos_token_id: u32,
+}
+
+impl LatexGrammar {
+    pub fn from_tokenizer(tokenizer: &Tokenizer) -> Self {
+        let vocab_size = tokenizer.get_vocab_size(true);
+        let token_texts: Vec<String> = (0..vocab_size as u32)
+            .map(|id| tokenizer.id_to_token(id).unwrap_or_default())
+            .collect();
+        let structural = token_texts
+            .iter()
+            .map(|text| text.contains(['\\', '{', '}']))
+            .collect();
+        let eos_token_id = tokenizer.token_to_id("</s>").unwrap_or(30000);
+        Self { token_texts, structural, eos_token_id }
+    }
+}
+
+/// 保证输出结构平衡的解码约束：屏蔽会产生非法结构的 token，以及仍有未闭合结构时的 EOS
+#[derive(Clone)]
+pub struct LatexConstraint {
+    grammar: Arc<LatexGrammar>,
+    state: LatexState,
+}
+
+impl LatexConstraint {
+    pub fn new(grammar: Arc<LatexGrammar>) -> Self {
+        Self { grammar, state: LatexState::default() }
+    }
+
+    /// 将不合法 token 的 logits 置为负无穷；若所有 token 都不合法则保持不变
+    pub fn mask_logits(&self, logits: &mut [f32]) {
+        let pending = self.state.is_pending();
+        let mut masked = Vec::new();
+        for (id, logit) in logits.iter().enumerate() {
+            if *logit == f32::NEG_INFINITY || !self.is_allowed(id, pending) {
+                masked.push(id);
+            }
+        }
+        if masked.len() == logits.len() {
+            return;
+        }
+        for id in masked {
+            logits[id] = f32::NEG_INFINITY;
+        }
+    }
+
+    /// 记录已选中的 token
+    pub fn advance(&mut self, token_id: u32) {
+        if token_id == self.grammar.eos_token_id {
+            return;
+        }
+        if let Some(text) = self.grammar.token_texts.get(token_id as usize) {
+            let mut state = self.state.clone();
+            // 所有 token 都被屏蔽时可能选中非法 token，此时保留原状态
+            if state.feed_str(text).is_ok() {
+                self.state = state;
+            }
+        }
+    }
+
+    fn is_allowed(&self, id: usize, pending: bool) -> bool {
+        if id as u32 == self.grammar.eos_token_id {
+            return self.state.can_finish();
+        }
+        let Some(text) = self.grammar.token_texts.get(id) else {
+            return true;
+        };
+        if !pending && !self.grammar.structural[id] {
+            return true;
+        }
+        let mut state = self.state.clone();
+        state.feed_str(text).is_ok()
+    }
+}
+
+/// 在内部选择器之前应用 `LatexConstraint` 的选择器
+pub struct ConstrainedSelector<S> {
+    inner: S,
+    constraint: LatexConstraint,
+}
+
+impl<S: TokenSelector> ConstrainedSelector<S> {
+    pub fn new(inner: S, constraint: LatexConstraint) -> Self {
+        Self { inner, constraint }
+    }
+}
+
+impl<S: TokenSelector> TokenSelector for ConstrainedSelector<S> {
+    fn select(&mut self, logits: &[f32], history: &[u32]) -> u32 {
+        let mut masked = logits.to_vec();
+        self.constraint.mask_logits(&mut masked);
+        let token_id = self.inner.select(&masked, history);
+        self.constraint.advance(token_id);
+        token_id
+    }
+
+    fn observe(&mut self, token_id: u32) {
+        self.inner.observe(token_id);
+        self.constraint.advance(token_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_tokens(tokens: &[&str]) -> Result<LatexState, Invalid> {
+        let mut state = LatexState::default();
+        for token in tokens {
+            state.feed_str(token)?;
+        }
+        Ok(state)
+    }
+
+    fn finishes(tokens: &[&str]) -> bool {
+        feed_tokens(tokens).map(|state| state.can_finish()).unwrap_or(false)
+    }
+
+    #[test]
+    fn begin_split_across_tokens() {
+        assert!(finishes(&["\\", "beg", "in", "{mat", "rix}", "a", "\\end", "{matrix}"]));
+        assert!(!finishes(&["\\", "beg", "in", "{matrix}", "a"]));
+        assert!(feed_tokens(&["\\begin{matrix}", "\\end{cases}"]).is_err());
+    }
+
+    #[test]
+    fn escaped_braces_and_line_breaks_are_text() {
+        assert!(finishes(&["\\{", "x", "\\}"]));
+        assert!(finishes(&["a", "\\\\", "b"]));
+        assert!(finishes(&["\\}"]));
+        assert!(feed_tokens(&["}"]).is_err());
+    }
+
+    #[test]
+    fn left_right_pairs() {
+        assert!(finishes(&["\\left(", "x", "\\right."]));
+        assert!(!finishes(&["\\left(", "x"]));
+        assert!(feed_tokens(&["\\right)"]).is_err());
+        assert!(feed_tokens(&["\\left(", "{", "\\right)"]).is_err());
+    }
+
+    #[test]
+    fn byte_level_whitespace_before_env_name() {
+        assert!(finishes(&["\\begin", "Ġ{", "array}", "\\end", "Ġ{array}"]));
+        assert!(feed_tokens(&["\\begin", "Ġx"]).is_err());
+    }
+
+    #[test]
+    fn unfinished_command_at_end() {
+        assert!(finishes(&["\\alpha"]));
+        assert!(!finishes(&["\\left"]));
+        assert!(!finishes(&["\\"]));
+    }
+
+    fn constraint(tokens: &[&str]) -> LatexConstraint {
+        let token_texts: Vec<String> = tokens.iter().map(|token| token.to_string()).collect();
+        let structural = token_texts.iter().map(|text| text.contains(['\\', '{', '}'])).collect();
+        let eos_token_id = tokens.iter().position(|&token| token == "</s>").unwrap() as u32;
+        LatexConstraint::new(Arc::new(LatexGrammar { token_texts, structural, eos_token_id }))
+    }
+
+    #[test]
+    fn masks_eos_until_groups_are_closed() {
+        let mut constraint = constraint(&["x", "{", "}", "</s>"]);
+        let mut logits = vec![0.0; 4];
+        constraint.mask_logits(&mut logits);
+        assert_eq!(logits, [0.0, 0.0, f32::NEG_INFINITY, 0.0]);
+
+        constraint.advance(1);
+        let mut logits = vec![0.0; 4];
+        constraint.mask_logits(&mut logits);
+        assert_eq!(logits, [0.0, 0.0, 0.0, f32::NEG_INFINITY]);
+
+        constraint.advance(2);
+        let mut logits = vec![0.0; 4];
+        constraint.mask_logits(&mut logits);
+        assert_eq!(logits, [0.0, 0.0, f32::NEG_INFINITY, 0.0]);
+    }
+
+    #[test]
+    fn keeps_logits_when_everything_is_masked() {
+        let constraint = constraint(&["}", "</s>"]);
+        let mut logits = vec![1.0, f32::NEG_INFINITY];
+        constraint.mask_logits(&mut logits);
+        assert_eq!(logits, [1.0, f32::NEG_INFINITY]);
+    }
+}
@@ -0,0 +1,57 @@
+/// 对 logits 做数值稳定的 log-softmax
+pub fn log_softmax(logits: &[f32]) -> Vec<f32> {
+    let max_logit = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum_exp = logits
+        .iter()
+        .map(|&x| (x - max_logit).exp())
+        .sum::<f32>()
+        .ln()
+        + max_logit;
+    logits.iter().map(|&x| x - log_sum_exp).collect()
+}
+
+/// 返回分数最高的 k 个下标，按分数从高到低排列
+pub fn top_k_indices(scores: &[f32], k: usize) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..scores.len()).collect();
+    let k = k.min(indices.len());
+    if k == 0 {
+        return Vec::new();
+    }
+    indices.select_nth_unstable_by(k - 1, |&a, &b| scores[b].total_cmp(&scores[a]));
+    indices.truncate(k);
+    indices.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    indices
+}
+
+/// 一步解码中被选中的 token 及其置信度
+#[derive(Debug, Clone, Copy)]
+pub struct TokenChoice {
+    pub token_id: u32,
+    /// 该 token 在 softmax 分布中的 log 概率
+    pub log_prob: f32,
+    /// 在所有 token 中的排名，0 表示概率最高
+    pub rank: usize,
+    /// 与另一个最可能 token 的 log 概率之差，贪心解码时为领先次优 token 的幅度
+    pub margin: f32,
+}
+
+impl TokenChoice {
+    /// 根据原始 logits 计算被选中 token 的置信度
+    pub fn from_logits(logits: &[f32], token_id: u32) -> Self {
+        let log_probs = log_softmax(logits);
+        Self::from_log_probs(&log_probs, token_id)
+    }
+
+    pub fn from_log_probs(log_probs: &[f32], token_id: u32) -> Self {
+        let index = token_id as usize;
+        let log_prob = log_probs.get(index).copied().unwrap_or(f32::NEG_INFINITY);
+        let rank = log_probs.iter().filter(|&&lp| lp > log_prob).count();
+        let best_other = log_probs
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != index)
+            .map(|(_, &lp)| lp)
+            .fold(f32::NEG_INFINITY, f32::max);
+        Self { token_id, log_prob, rank, margin: log_prob - best_other }
+    }
+}
@@ -1,9 +1,29 @@
 mod onnx_inference;
 mod process_img;
-mod temporary_img;
+mod preprocess_config;
+mod binarize;
 mod check_inference;
+mod logits;
+mod beam_search;
+mod sampling;
+mod decoder_spec;
+mod kv_cache;
+mod encoder_output;
+mod latex_constraint;
+mod generation;
+mod batching;
+mod token_stream;
 
-pub use onnx_inference::OrtInferenceSession;
-pub use temporary_img::TemporaryData;
-pub use process_img::process_image_with_padding;
-pub use check_inference::check_repetition;
\ No newline at end of file
+pub use onnx_inference::{ImageInput, OrtInferenceSession};
+pub use process_img::{process_image_with_padding, Preprocessor};
+pub use preprocess_config::{Alignment, ColorMode, ImageSize, PreprocessConfig, Resample, PREPROCESSOR_CONFIG_FILE};
+pub use check_inference::check_repetition;
+pub use beam_search::{BeamSearchConfig, BeamHypothesis};
+pub use sampling::{TokenSelector, GreedySelector, Sampler, SamplingConfig};
+pub use logits::TokenChoice;
+pub use kv_cache::KvCache;
+pub use encoder_output::EncoderOutput;
+pub use latex_constraint::{ConstrainedSelector, LatexConstraint};
+pub use generation::{CancelToken, GenerationOutput, StopReason, StoppingCriteria};
+pub use batching::{BatchConfig, BatchScheduler};
+pub use token_stream::{GenerateOptions, TokenEvent, TokenIter};
@@ -1,193 +1,262 @@
 //src/onnx_inference_copy.rs
 use anyhow::Ok;
 use ort::session::Session;
-use ort::session::SessionBuilder;
-use ort::GraphOptimizationLevel;
-use ort::LoggingLevel;
-use ort::Environment;
-use ort::Value;
-use ort::tensor::OrtOwnedTensor;
+use ort::session::builder::{GraphOptimizationLevel, SessionBuilder};
+use ort::session::SessionInputValue;
+use ort::value::Tensor;
 
 use tokenizers::Tokenizer;
-use ndarray::{Array, ArrayBase, OwnedRepr, Dim, ArrayD, IxDyn, CowArray, IxDynImpl};
+use ndarray::{Array, ArrayD, IxDyn};
 use std::path::PathBuf;
 
-use crate::process_image_with_padding;
+use super::process_img::Preprocessor;
+use super::preprocess_config::PreprocessConfig;
+use super::sampling::TokenSelector;
+use super::logits::TokenChoice;
+use super::decoder_spec::DecoderSpec;
+use super::kv_cache::KvCache;
+use super::encoder_output::EncoderOutput;
+use super::latex_constraint::{LatexConstraint, LatexGrammar};
+use std::sync::Arc;
+
+/// 推理的输入图片。`Pixels` 为 `preprocess_image` 已经处理好的 (1, C, H, W) 张量，
+/// 调用方需要检查或保存编码器实际看到的内容时先自行预处理，避免推理时再处理一遍
+pub enum ImageInput {
+    Image(image::DynamicImage),
+    Pixels(ArrayD<f32>),
+}
+
+impl From<image::DynamicImage> for ImageInput {
+    fn from(image: image::DynamicImage) -> Self {
+        ImageInput::Image(image)
+    }
+}
 
 pub struct OrtInferenceSession {
     encoder_session: Session,
     decoder_session: Session,
-    tokenizer: Tokenizer
+    decoder_spec: DecoderSpec,
+    tokenizer: Tokenizer,
+    latex_grammar: Arc<LatexGrammar>,
+    preprocessor: Preprocessor,
 }
 
 
+
 impl OrtInferenceSession {
-    pub fn new(model_folder: &str, _tokenizer_path: &str) -> anyhow::Result<Self> {
-        let environment = Environment::builder()
+    /// `intra_threads` 为每个会话的算子内线程数，0 表示使用 ONNX Runtime 的默认值
+    pub fn new(model_folder: &str, tokenizer_path: &str, intra_threads: usize) -> anyhow::Result<Self> {
+        ort::init()
             .with_name("mixtex_environment")
-            .with_log_level(LoggingLevel::Verbose)
-            .build()?
-            .into_arc();
+            .commit()?;
 
         // Create a temporary instance
         let encoder_path = PathBuf::from(model_folder).join("encoder_model.onnx");
-        let encoder_session = SessionBuilder::new(&environment)?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_inter_threads(1)?
-            .with_model_from_file(encoder_path)?;
+        let encoder_session = Self::session_builder(intra_threads)?
+            .commit_from_file(encoder_path)?;
 
         let decoder_path = PathBuf::from(model_folder).join("decoder_model.onnx");
-        let decoder_session = SessionBuilder::new(&environment)?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_inter_threads(1)?
-            .with_model_from_file(decoder_path)?;
-        let tokenizer = Tokenizer::from_file(_tokenizer_path)
+        let decoder_session = Self::session_builder(intra_threads)?
+            .commit_from_file(decoder_path)?;
+        // 层数、注意力头数与输入顺序都从模型元数据中读取
+        let decoder_spec = DecoderSpec::from_session(&decoder_session)?;
+        let tokenizer = Tokenizer::from_file(tokenizer_path)
             .map_err(|e| anyhow::anyhow!("Failed to load tokenizer: {}", e))?;
+        let latex_grammar = Arc::new(LatexGrammar::from_tokenizer(&tokenizer));
+        // 模型目录中有 preprocessor_config.json 时按其中的参数预处理
+        let preprocessor = Preprocessor::new(PreprocessConfig::from_model_folder(&PathBuf::from(model_folder))?);
 
         Ok(Self {
             encoder_session,
             decoder_session,
-            tokenizer
+            decoder_spec,
+            tokenizer,
+            latex_grammar,
+            preprocessor,
         })
     }
 
+    fn session_builder(intra_threads: usize) -> anyhow::Result<SessionBuilder> {
+        let builder = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .with_inter_threads(1)?;
+        if intra_threads == 0 {
+            return Ok(builder);
+        }
+        Ok(builder.with_intra_threads(intra_threads)?)
+    }
+
     pub fn get_tokenizer(&self) -> &Tokenizer {
         &self.tokenizer
     }
 
-    pub fn encode_image(&self, input_image: image::DynamicImage) -> anyhow::Result<ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>> {
-        let image_data = process_image_with_padding(input_image, "processed_image.png")?;
+    pub fn preprocessor(&self) -> &Preprocessor {
+        &self.preprocessor
+    }
+
+    /// 创建一个初始状态的 LaTeX 结构约束
+    pub fn latex_constraint(&self) -> LatexConstraint {
+        LatexConstraint::new(Arc::clone(&self.latex_grammar))
+    }
+
+    /// 编码器与解码器共同支持的最大 batch，模型导出为动态 batch 时为 None
+    pub fn max_batch_size(&self) -> Option<usize> {
+        let encoder_batch = self.encoder_session.inputs[0]
+            .input_type
+            .tensor_dimensions()
+            .and_then(|dims| dims.first().copied())
+            .filter(|&dim| dim > 0)
+            .map(|dim| dim as usize);
+        match (encoder_batch, self.decoder_spec.batch_size) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn encode_image(&self, input_image: impl Into<ImageInput>) -> anyhow::Result<EncoderOutput> {
+        let dyn_image = self.pixel_values(input_image.into())?;
+        self.encode_pixels(dyn_image)
+    }
+
+    /// 返回编码器的输入张量，原始图片在这里预处理
+    pub fn pixel_values(&self, input: ImageInput) -> anyhow::Result<ArrayD<f32>> {
+        match input {
+            ImageInput::Image(input_image) => self.preprocess_image(input_image),
+            ImageInput::Pixels(pixel_values) => Ok(pixel_values),
+        }
+    }
+
+    /// 预处理为形状 (1, C, H, W) 的张量，所有图片都被填充到相同尺寸，可以直接拼成 batch
+    pub fn preprocess_image(&self, input_image: image::DynamicImage) -> anyhow::Result<ArrayD<f32>> {
+        let image_data = self.preprocessor.process(input_image)?;
 
         // Step 2: 转为动态维度 (IxDyn)
-        let dyn_image: ArrayD<f32> = image_data.into_dyn();
-
-        // ✅ Step 3: 构建 CowArray（关键）
-        let cow_array: CowArray<f32, IxDyn> = CowArray::from(dyn_image);
-
-        // 4. 创建 ONNX 输入
-        let allocator = self.encoder_session.allocator();
-        let input = Value::from_array(allocator, &cow_array)?;
-
-        let outputs = self.encoder_session.run(vec![input])?;
-        let outpu_ort: OrtOwnedTensor<'_, f32, Dim<IxDynImpl>> = outputs[0].try_extract::<f32>()?;
-        let output_array = outpu_ort.view().to_owned();
-        // println!("Encoder outputs shape: {:?}", outpu_ort.view().shape());
-
-        Ok(output_array)
-    }
-
-    
-    // pub fn image_inference(&self, input_image: image::DynamicImage) -> anyhow::Result<String> {
-    //     let eos_token_id: u32 = self.tokenizer.token_to_id("</s>").unwrap();
-    //     let bos_token_id: u32 = self.tokenizer.token_to_id("<s>").unwrap();
-
-    //     let max_len = 512;
-    //     let (decoder_outputs, next_token_id, encoder_output) = self.init_inference(input_image)?;
-
-    //     let mut token_id_arr = vec![bos_token_id, next_token_id];
-    //     let mut decoder_inputs = decoder_outputs;
-    //     let mut encoder_input = encoder_output;
-    //     let mut input_token_id = next_token_id;
-
-    //     for _i in 0..max_len {
-    //         let (decoder_outputs, next_token_id, encoder_hidden_states) = self.single_inference(decoder_inputs, input_token_id, encoder_input)?;
-    //         token_id_arr.push(next_token_id);
-    //         if next_token_id == eos_token_id {
-    //             break;
-    //         }
-    //         decoder_inputs = decoder_outputs;
-    //         encoder_input = encoder_hidden_states;
-    //         input_token_id = next_token_id;
-    //     }
-    //     let decode_res = self.tokenizer.decode(&token_id_arr, true)
-    //         .map_err(|e| anyhow::anyhow!("Failed to decode tokens: {}", e))?;
-
-    //     Ok(decode_res)
-    // }
-
-    pub fn single_inference(&self, input_vec: Vec<Value<'static>>, input_token_value: u32, encoder_hidden_states: ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>) -> anyhow::Result<(Vec<Value<'static>>, u32, ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>)> {
-        let input_ids = Array::from_shape_vec(IxDyn(&[1, 1]), vec![input_token_value as i64]).to_owned()?;
-        let cow_input_ids = CowArray::from(input_ids);
-        let encoder_hidden_states_copy = encoder_hidden_states.to_owned();
-        let cow_encoder_hidden_states = CowArray::from(encoder_hidden_states);
-        let allocator = self.decoder_session.allocator();
-        
-        let input  = Value::from_array(allocator, &cow_input_ids)?;
-        let encoder_input = Value::from_array(allocator, &cow_encoder_hidden_states)?;
-
-        let unsafe_input = unsafe {
-            std::mem::transmute::<Value<'_>, Value<'static>>(input)
-        };
-        let unsafe_encoder_input: Value<'static> = unsafe {
-            std::mem::transmute::<Value<'_>, Value<'static>>(encoder_input)
-        };
-
-        let mut decoder_inputs = vec![unsafe_input, unsafe_encoder_input];
-        decoder_inputs.extend(input_vec);
-
-        let mut decoder_outputs: Vec<Value<'static>> = self.decoder_session.run(decoder_inputs)?;
-        let logits_output = decoder_outputs[0].try_extract::<f32>()?;
-        let next_token_id: u32 = logits_output
-            .view()
-            .iter()
-            .enumerate()
-            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-            .map(|(idx, _)| idx as u32)
-            .unwrap();
-        decoder_outputs.remove(0); // Remove logits output
-        Ok((decoder_outputs, next_token_id, encoder_hidden_states_copy))
-    }
-
-    pub fn init_inference(&self, input_image: image::DynamicImage) -> anyhow::Result<(Vec<Value<'static>>, u32, ArrayBase<OwnedRepr<f32>, Dim<IxDynImpl>>)> {
-        let encoder_hidden_states = self.encode_image(input_image)?;
-        let bos_token_id: u32 = self.tokenizer.token_to_id("<s>").unwrap();
-
-        let num_layers = 6;
-        let num_heads = 12;
-        let seq_len = 1;
-        let head_dim = 64;
-        let batch_size = 1;
-
-        let input_ids = {
-            Array::from_shape_vec(
-                IxDyn(&[batch_size, seq_len]),
-                vec![bos_token_id as i64],
-            )?
-        };
-
-        let past_tensor = ArrayD::<f32>::zeros(IxDyn(&[batch_size, num_heads, 0, head_dim]));
-        let mut past_keys = Vec::with_capacity(num_layers);
-        let mut past_values = Vec::with_capacity(num_layers);
-        for _i in 0..num_layers {
-            past_keys.push(CowArray::from(past_tensor.clone()));
-            past_values.push(CowArray::from(past_tensor.clone()));
+        Ok(image_data.into_dyn())
+    }
+
+    /// 编码形状为 (B, C, H, W) 的已预处理图片，返回 (B, S, H) 的 encoder hidden states
+    pub fn encode_pixels(&self, dyn_image: ArrayD<f32>) -> anyhow::Result<EncoderOutput> {
+        // 创建 ONNX 输入，张量持有自己的数据
+        let input = Tensor::from_array(dyn_image)?;
+
+        let outputs = self.encoder_session.run([SessionInputValue::from(input)])?;
+        // 直接持有编码器的输出张量，后续解码步骤共享它
+        let (_name, output) = outputs
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Encoder returned no output"))?;
+        // println!("Encoder outputs shape: {:?}", output.shape());
+
+        EncoderOutput::from_value(output)
+    }
+
+    /// 输入一个 token 前进一步，并用 `selector` 选出下一个 token
+    pub fn single_inference(&self, kv_cache: &KvCache, input_token_value: u32, encoder_hidden_states: &EncoderOutput, selector: &mut dyn TokenSelector, history: &[u32]) -> anyhow::Result<(KvCache, TokenChoice)> {
+        let (logits, present_key_values) = self.decoder_step(kv_cache, input_token_value, encoder_hidden_states)?;
+        let next_token_id = selector.select(&logits, history);
+        let choice = TokenChoice::from_logits(&logits, next_token_id);
+        Ok((present_key_values, choice))
+    }
+
+    /// 用 tokenizer 将用户已修正的前缀转为 token id（不含 BOS）
+    pub fn encode_prefix(&self, prefix: &str) -> anyhow::Result<Vec<u32>> {
+        let encoding = self.tokenizer.encode(prefix, false)
+            .map_err(|e| anyhow::anyhow!("Failed to encode prefix: {}", e))?;
+        Ok(encoding.get_ids().to_vec())
+    }
+
+    /// 将生成的 token id 还原为 LaTeX 文本，跳过 BOS / EOS 等特殊 token
+    pub fn decode_tokens(&self, token_ids: &[u32]) -> anyhow::Result<String> {
+        self.tokenizer.decode(token_ids, true)
+            .map_err(|e| anyhow::anyhow!("Failed to decode tokens: {}", e))
+    }
+
+    /// 返回长度为 0 的初始缓存
+    pub fn empty_kv_cache(&self, batch_size: usize) -> anyhow::Result<KvCache> {
+        KvCache::empty(&self.decoder_spec, batch_size)
+    }
+
+    /// 执行一步解码，返回该步的 logits 与新的缓存。多条 beam 通过 `KvCache::reorder` 共享同一前缀。
+    pub fn decoder_step(&self, kv_cache: &KvCache, input_token_id: u32, encoder_hidden_states: &EncoderOutput) -> anyhow::Result<(Vec<f32>, KvCache)> {
+        let (mut logits, present_key_values) = self.decoder_step_batch(kv_cache, &[input_token_id], encoder_hidden_states)?;
+        let logits = logits.pop().ok_or_else(|| anyhow::anyhow!("Decoder returned empty logits"))?;
+        Ok((logits, present_key_values))
+    }
+
+    /// 对一个 batch 同时执行一步解码。batch 内所有序列的 past 长度必须相同，
+    /// `input_token_ids[i]` 对应第 i 行，返回每行的 logits。
+    pub fn decoder_step_batch(&self, kv_cache: &KvCache, input_token_ids: &[u32], encoder_hidden_states: &EncoderOutput) -> anyhow::Result<(Vec<Vec<f32>>, KvCache)> {
+        self.decoder_step_padded(kv_cache, input_token_ids, encoder_hidden_states, &vec![0; input_token_ids.len()])
+    }
+
+    /// 解码器是否支持在 past 开头填充，见 `decoder_step_padded`
+    pub fn supports_padding(&self) -> bool {
+        self.decoder_spec.supports_padding()
+    }
+
+    /// 与 `decoder_step_batch` 相同，但第 i 行的 past 开头有 `padding[i]` 个填充位置：
+    /// 这些位置在 `attention_mask` 中被屏蔽，`position_ids` 只计算真实的 token。
+    /// 填充不为 0 时要求 `supports_padding()`。
+    pub fn decoder_step_padded(&self, kv_cache: &KvCache, input_token_ids: &[u32], encoder_hidden_states: &EncoderOutput, padding: &[usize]) -> anyhow::Result<(Vec<Vec<f32>>, KvCache)> {
+        let batch_size = input_token_ids.len();
+        if padding.len() != batch_size {
+            anyhow::bail!("Expected {} padding lengths, got {}", batch_size, padding.len());
         }
+        if padding.iter().any(|&pad| pad > 0) && !self.supports_padding() {
+            anyhow::bail!("Decoder model does not export attention_mask and position_ids, cannot decode padded rows");
+        }
+        let token_values = input_token_ids.iter().map(|&id| id as i64).collect();
+        let input_ids = Array::from_shape_vec(IxDyn(&[batch_size, 1]), token_values)?;
+
+        // attention_mask 覆盖 past 与本步输入，填充位置为 0
+        let past_len = kv_cache.seq_len();
+        let attention_mask = Array::from_shape_fn(IxDyn(&[batch_size, past_len + 1]), |index| {
+            i64::from(index[1] >= padding[index[0]])
+        });
+        let position_values = padding.iter().map(|&pad| past_len.saturating_sub(pad) as i64).collect();
+        let position_ids = Array::from_shape_vec(IxDyn(&[batch_size, 1]), position_values)?;
+
+        // past 与 encoder hidden states 都以视图方式传入，只有每步变化的小张量需要新建
+        let decoder_inputs = self.decoder_spec.bind_inputs(
+            SessionInputValue::from(Tensor::from_array(input_ids)?),
+            encoder_hidden_states.as_input(),
+            SessionInputValue::from(Tensor::from_array(attention_mask)?),
+            SessionInputValue::from(Tensor::from_array(position_ids)?),
+            kv_cache.as_inputs(),
+        )?;
+
+        let decoder_outputs = self.decoder_session.run(decoder_inputs.as_slice())?;
+        let decoder_outputs = decoder_outputs.into_iter().map(|(_name, value)| value).collect();
+        let (logits_value, present_outputs) = self.decoder_spec.split_outputs(decoder_outputs)?;
+        // logits 形状为 (B, 1, vocab)
+        let logits_output = logits_value.try_extract_tensor::<f32>()?;
+        let logits: Vec<Vec<f32>> = logits_output
+            .outer_iter()
+            .map(|row| row.iter().copied().collect())
+            .collect();
+
+        // present 直接作为下一步的 past，不复制
+        Ok((logits, KvCache::from_values(present_outputs)?))
+    }
+
+    /// 编码图片并执行第一步解码。`prefix` 非空时先将其逐个 token 强制输入解码器以构建 KV 缓存，
+    /// 返回的是紧接在前缀之后的第一个生成 token。
+    pub fn init_inference(&self, input_image: impl Into<ImageInput>, prefix: &[u32], selector: &mut dyn TokenSelector) -> anyhow::Result<(KvCache, TokenChoice, EncoderOutput)> {
+        let encoder_hidden_states = self.encode_image(input_image)?;
+        let bos_token_id: u32 = self.tokenizer.token_to_id("<s>").unwrap_or(0);
+
+        let (mut logits, mut kv_cache) = self.decoder_step(&self.empty_kv_cache(1)?, bos_token_id, &encoder_hidden_states)?;
 
-        let cow_input_ids = CowArray::from(input_ids);
-        let cow_encoder_hidden_states = CowArray::from(encoder_hidden_states.clone());
-        let encoder_hidden_states_copy = encoder_hidden_states.to_owned();
-        let allocator = self.decoder_session.allocator();
-        let input = Value::from_array(allocator, &cow_input_ids)?;
-        let encoder_input = Value::from_array(allocator, &cow_encoder_hidden_states)?;
-        
-        let mut decoder_inputs = Vec::new();
-        decoder_inputs.push(input);
-        decoder_inputs.push(encoder_input);
-
-        for i in 0..num_layers {
-            decoder_inputs.push(Value::from_array(allocator, &past_keys[i])?);
-            decoder_inputs.push(Value::from_array(allocator, &past_values[i])?);
+        // teacher forcing：忽略前缀位置上的预测，直接输入前缀 token
+        let mut history = vec![bos_token_id];
+        for &forced_token_id in prefix {
+            selector.observe(forced_token_id);
+            history.push(forced_token_id);
+            (logits, kv_cache) = self.decoder_step(&kv_cache, forced_token_id, &encoder_hidden_states)?;
         }
-        let mut decoder_outputs: Vec<Value<'static>> = self.decoder_session.run(decoder_inputs)?;
-        let logits_output = decoder_outputs[0].try_extract::<f32>()?;
-        let next_token_id: u32 = logits_output
-            .view()
-            .iter()
-            .enumerate()
-            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-            .map(|(idx, _)| idx as u32)
-            .unwrap();
-        decoder_outputs.remove(0); // Remove logits output
-        Ok((decoder_outputs, next_token_id, encoder_hidden_states_copy))
-    }
-}
\ No newline at end of file
+
+        let next_token_id = selector.select(&logits, &history);
+        let choice = TokenChoice::from_logits(&logits, next_token_id);
+        Ok((kv_cache, choice, encoder_hidden_states))
+    }
+}
@@ -0,0 +1,226 @@
+use std::path::Path;
+use anyhow::Context;
+use image::imageops::FilterType;
+use serde::Deserialize;
+
+/// 模型目录中的预处理配置文件名，与 HuggingFace 导出的文件相同
+pub const PREPROCESSOR_CONFIG_FILE: &str = "preprocessor_config.json";
+
+/// 图片预处理参数。字段名与 HuggingFace 的 `preprocessor_config.json` 一致，文件中的其他字段会被忽略；
+/// `normalize_polarity`、`polarity_tolerance`、`color_mode`、`adaptive_*`、`do_trim`、`trim_*`、`pad_color` 与 `alignment` 是本项目额外支持的字段。
+/// 未给出的字段使用与训练时相同的默认值：448x448、bicubic、白色填充、居中、mean/std 均为 0.5。
+/// 深色背景反色与缩放前裁掉空白边距是本项目额外的处理，训练时没有使用，默认关闭
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PreprocessConfig {
+    /// 背景比前景暗时（深色主题的编辑器、深色幻灯片）反色为浅底深字
+    pub normalize_polarity: bool,
+    /// 判断明暗时，每个通道与背景色相差超过该值的像素视为前景
+    pub polarity_tolerance: u8,
+    pub color_mode: ColorMode,
+    /// `adaptive` 模式下计算局部均值的窗口半径
+    pub adaptive_radius: u32,
+    /// `adaptive` 模式下像素比局部均值暗多少才算前景
+    pub adaptive_offset: f32,
+    /// 缩放前裁掉内容四周与背景色相同的边距
+    pub do_trim: bool,
+    /// 每个通道与背景色相差不超过该值的像素视为背景
+    pub trim_tolerance: u8,
+    /// 裁剪时在内容四周保留的像素数（原图尺寸）
+    pub trim_margin: u32,
+    pub size: ImageSize,
+    pub resample: Resample,
+    pub do_rescale: bool,
+    pub rescale_factor: f32,
+    pub do_normalize: bool,
+    pub image_mean: [f32; 3],
+    pub image_std: [f32; 3],
+    /// 缩放后填充空白区域所用的 RGB 颜色
+    pub pad_color: [u8; 3],
+    pub alignment: Alignment,
+}
+
+impl Default for PreprocessConfig {
+    fn default() -> Self {
+        Self {
+            normalize_polarity: false,
+            polarity_tolerance: 32,
+            color_mode: ColorMode::default(),
+            adaptive_radius: 15,
+            adaptive_offset: 10.0,
+            do_trim: false,
+            trim_tolerance: 32,
+            trim_margin: 8,
+            size: ImageSize::default(),
+            resample: Resample::default(),
+            do_rescale: true,
+            rescale_factor: 1.0 / 255.0,
+            do_normalize: true,
+            image_mean: [0.5; 3],
+            image_std: [0.5; 3],
+            pad_color: [255; 3],
+            alignment: Alignment::default(),
+        }
+    }
+}
+
+impl PreprocessConfig {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read preprocessor config {}", path.display()))?;
+        let config: Self = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid preprocessor config {}", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 读取模型目录下的 `preprocessor_config.json`，文件不存在时使用默认值
+    pub fn from_model_folder(model_folder: &Path) -> anyhow::Result<Self> {
+        let path = model_folder.join(PREPROCESSOR_CONFIG_FILE);
+        if path.exists() {
+            Self::from_file(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let (width, height) = self.size.dimensions();
+        if width == 0 || height == 0 {
+            anyhow::bail!("Preprocessor size must be positive, got {}x{}", width, height);
+        }
+        if self.image_std.contains(&0.0) {
+            anyhow::bail!("Preprocessor image_std must not contain 0");
+        }
+        if self.do_rescale && self.rescale_factor == 0.0 {
+            anyhow::bail!("Preprocessor rescale_factor must not be 0");
+        }
+        Ok(())
+    }
+}
+
+/// 目标尺寸，兼容 HuggingFace 的 `448` 与 `{"height": 448, "width": 448}` 两种写法。
+/// `{"shortest_edge": 448}` 表示按短边等比缩放，得到的尺寸随图片变化，无法填充成统一尺寸的 batch，读取时报错
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "ImageSizeValue")]
+pub enum ImageSize {
+    Square(u32),
+    HeightWidth { height: u32, width: u32 },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ImageSizeValue {
+    Square(u32),
+    HeightWidth { height: u32, width: u32 },
+    ShortestEdge { shortest_edge: u32 },
+}
+
+impl TryFrom<ImageSizeValue> for ImageSize {
+    type Error = String;
+
+    fn try_from(value: ImageSizeValue) -> Result<Self, Self::Error> {
+        match value {
+            ImageSizeValue::Square(size) => Ok(ImageSize::Square(size)),
+            ImageSizeValue::HeightWidth { height, width } => Ok(ImageSize::HeightWidth { height, width }),
+            ImageSizeValue::ShortestEdge { shortest_edge } => Err(format!(
+                "size {{\"shortest_edge\": {}}} keeps the aspect ratio and is not supported, use {{\"height\": {}, \"width\": {}}} instead",
+                shortest_edge, shortest_edge, shortest_edge
+            )),
+        }
+    }
+}
+
+impl Default for ImageSize {
+    fn default() -> Self {
+        ImageSize::HeightWidth { height: 448, width: 448 }
+    }
+}
+
+impl ImageSize {
+    /// 返回 (宽, 高)
+    pub fn dimensions(&self) -> (u32, u32) {
+        match *self {
+            ImageSize::Square(size) => (size, size),
+            ImageSize::HeightWidth { height, width } => (width, height),
+        }
+    }
+}
+
+/// 缩放时使用的插值方法，可以写成 PIL 的整数编号（HuggingFace 的写法）或名称
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "ResampleValue")]
+pub enum Resample {
+    Nearest,
+    Bilinear,
+    #[default]
+    Bicubic,
+    Gaussian,
+    Lanczos,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ResampleValue {
+    Pil(u8),
+    Name(String),
+}
+
+impl TryFrom<ResampleValue> for Resample {
+    type Error = String;
+
+    fn try_from(value: ResampleValue) -> Result<Self, Self::Error> {
+        match value {
+            // PIL: 0 NEAREST, 1 LANCZOS, 2 BILINEAR, 3 BICUBIC, 4 BOX, 5 HAMMING；后两种没有对应实现，按 bilinear 处理
+            ResampleValue::Pil(0) => Ok(Resample::Nearest),
+            ResampleValue::Pil(1) => Ok(Resample::Lanczos),
+            ResampleValue::Pil(2 | 4 | 5) => Ok(Resample::Bilinear),
+            ResampleValue::Pil(3) => Ok(Resample::Bicubic),
+            ResampleValue::Pil(code) => Err(format!("unknown PIL resample code {}", code)),
+            ResampleValue::Name(name) => match name.to_ascii_lowercase().as_str() {
+                "nearest" => Ok(Resample::Nearest),
+                "bilinear" | "triangle" => Ok(Resample::Bilinear),
+                "bicubic" | "catmull_rom" | "catmullrom" => Ok(Resample::Bicubic),
+                "gaussian" => Ok(Resample::Gaussian),
+                "lanczos" | "lanczos3" => Ok(Resample::Lanczos),
+                _ => Err(format!("unknown resample filter {:?}", name)),
+            },
+        }
+    }
+}
+
+impl Resample {
+    pub fn filter_type(self) -> FilterType {
+        match self {
+            Resample::Nearest => FilterType::Nearest,
+            Resample::Bilinear => FilterType::Triangle,
+            Resample::Bicubic => FilterType::CatmullRom,
+            Resample::Gaussian => FilterType::Gaussian,
+            Resample::Lanczos => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// 归一化之前的颜色处理。`otsu` 与 `adaptive` 二值化时暗像素为黑色、亮像素为白色，可以去掉彩色背景；
+/// 二值化不改变明暗关系，深色背景的图片需要同时开启 `normalize_polarity` 才能得到白底黑字
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMode {
+    /// 保留原有颜色
+    #[default]
+    Rgb,
+    Grayscale,
+    /// 全局 Otsu 阈值
+    Otsu,
+    /// 局部均值阈值，适合背景不均匀的图片
+    Adaptive,
+}
+
+/// 缩放后的图片在目标画布中的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Alignment {
+    #[default]
+    Center,
+    TopLeft,
+}
@@ -1,129 +1,283 @@
 //src//process_img.rs
 use image::{
-    imageops::FilterType,
-    Rgba,
+    DynamicImage,
+    GenericImageView,
     ImageBuffer,
+    Rgb,
+    RgbImage,
+    Rgba,
     RgbaImage,
-    DynamicImage, // Retain DynamicImage import as it's the return type of decode()
-    GenericImageView, // <-- FIX 2: Import GenericImageView trait
 };
 
-use image::buffer::ConvertBuffer; // <-- FIX 3: Import ConvertBuffer trait
-use ndarray::{Array3, Axis, ArrayBase, OwnedRepr, Dim};
+use image::buffer::ConvertBuffer;
+use ndarray::{Array4, ArrayView4};
 use anyhow::Context;
+use std::path::Path;
 
-pub fn process_image_with_padding(img: DynamicImage, _save_path: &str) -> anyhow::Result<ArrayBase<OwnedRepr<f32>, Dim<[usize; 4]>>> {
-    // 2. Resize the image, keeping the aspect ratio
-    // Use dimensions() method from GenericImageView trait
-    let (orig_width, orig_height) = img.dimensions(); // This call now works after importing GenericImageView
-    let (new_width, new_height) = if orig_width > orig_height {
-        (448, (orig_height as f32 * 448.0 / orig_width as f32).round() as u32)
-    } else {
-        ((orig_width as f32 * 448.0 / orig_height as f32).round() as u32, 448)
-    };
+use super::binarize::{adaptive_threshold, otsu_threshold, threshold};
+use super::preprocess_config::{Alignment, ColorMode, PreprocessConfig};
 
-    // Resize the original dynamic image
-    let resized = img.resize_exact(new_width, new_height, FilterType::CatmullRom); // resized is DynamicImage
+/// 按 `PreprocessConfig` 把图片转换为编码器输入：统一为浅色背景 → 灰度 / 二值化 → 裁掉空白边距 →
+/// 等比缩放 → 填充到目标尺寸 → 归一化为 (1, C, H, W)
+#[derive(Debug, Clone, Default)]
+pub struct Preprocessor {
+    config: PreprocessConfig,
+}
 
-    // Convert the resized image to RGBA for overlay compatibility
-    let resized_rgba = resized.to_rgba8(); // resized_rgba is RgbaImage
+impl Preprocessor {
+    pub fn new(config: PreprocessConfig) -> Self {
+        Self { config }
+    }
 
+    pub fn config(&self) -> &PreprocessConfig {
+        &self.config
+    }
 
-    // 3. Create a new image with a white background (448x448), using RGBA
-    let mut padded_img_rgba = RgbaImage::new(448, 448);
-    for (_x, _y, pixel) in padded_img_rgba.enumerate_pixels_mut() {
-        *pixel = Rgba([255, 255, 255, 255]); // White background
+    pub fn process(&self, img: DynamicImage) -> anyhow::Result<Array4<f32>> {
+        let normalized = self.normalize_polarity(img);
+        let converted = self.convert_color(normalized);
+        let trimmed = self.trim(converted);
+        let resized = self.resize(&trimmed);
+        let padded = self.pad(&resized);
+        self.to_tensor(&padded)
     }
 
-    // 4. Calculate the offset to center the resized image
-    let offset_x = (448 - new_width) / 2;
-    let offset_y = (448 - new_height) / 2;
+    /// 以图片边框的颜色作为背景色，与背景有明显差别的像素作为前景；背景比前景暗时反色（透明度不变）
+    pub fn normalize_polarity(&self, img: DynamicImage) -> DynamicImage {
+        if !self.config.normalize_polarity {
+            return img;
+        }
+        let rgba = img.to_rgba8();
+        let Some(background) = border_color(&rgba) else {
+            return img;
+        };
+        let tolerance = self.config.polarity_tolerance;
+        let (sum, count) = rgba
+            .pixels()
+            .filter(|pixel| is_foreground(pixel, background, tolerance))
+            .fold((0f64, 0u64), |(sum, count), pixel| (sum + luminance([pixel[0], pixel[1], pixel[2]]) as f64, count + 1));
+        if count == 0 || luminance(background) as f64 >= sum / count as f64 {
+            return img;
+        }
+        let mut img = img;
+        img.invert();
+        img
+    }
 
-    // 5. Place the resized RGBA image onto the white RGBA background
-    // Use u32 offsets converted to i64
-    image::imageops::overlay(&mut padded_img_rgba, &resized_rgba, offset_x.into(), offset_y.into());
+    /// 按 `color_mode` 转为灰度或二值图，透明区域先与填充色混合
+    pub fn convert_color(&self, img: DynamicImage) -> DynamicImage {
+        if self.config.color_mode == ColorMode::Rgb {
+            return img;
+        }
+        let [r, g, b] = self.config.pad_color;
+        let mut canvas = RgbaImage::from_pixel(img.width(), img.height(), Rgba([r, g, b, 255]));
+        image::imageops::overlay(&mut canvas, &img.to_rgba8(), 0, 0);
+        let gray = DynamicImage::ImageRgba8(canvas).to_luma8();
 
-    // 6. Convert the padded image from RGBA (4 channels) to RGB (3 channels)
-    // Use the convert method from ConvertBuffer trait on the instance
-    let padded_img_rgb: ImageBuffer<image::Rgb<u8>, Vec<u8>> = padded_img_rgba.convert(); // <-- FIX 3: Call convert() on the instance
-    let (width, height) = padded_img_rgb.dimensions(); // Use the RGB image dimensions
+        let converted = match self.config.color_mode {
+            ColorMode::Otsu => threshold(&gray, otsu_threshold(&gray)),
+            ColorMode::Adaptive => adaptive_threshold(&gray, self.config.adaptive_radius, self.config.adaptive_offset),
+            _ => gray,
+        };
+        DynamicImage::ImageLuma8(converted)
+    }
 
-    // 7. Convert to ndarray (H, W, C)
-    let raw_pixels = padded_img_rgb.into_raw(); // Vec<u8>, now contains H*W*3 bytes
+    /// 以图片边框的颜色作为背景色，裁剪到非背景像素的包围盒并保留 `trim_margin` 的边距。
+    /// 透明像素视为背景；整张图都是背景时原样返回
+    pub fn trim(&self, img: DynamicImage) -> DynamicImage {
+        if !self.config.do_trim {
+            return img;
+        }
+        let rgba = img.to_rgba8();
+        let (width, height) = rgba.dimensions();
+        let background = border_color(&rgba).unwrap_or(self.config.pad_color);
+        let tolerance = self.config.trim_tolerance;
 
-    // 8. Convert to f32 and rescale [0, 255] -> [0, 1]
-    let raw_pixels_f32: Vec<f32> = raw_pixels.iter()
-        .map(|&x| x as f32 / 255.0)
-        .collect();
+        // (min_x, min_y, max_x, max_y)
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+        for (x, y, pixel) in rgba.enumerate_pixels() {
+            if !is_foreground(pixel, background, tolerance) {
+                continue;
+            }
+            bounds = Some(match bounds {
+                Some((min_x, min_y, max_x, max_y)) => (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+                None => (x, y, x, y),
+            });
+        }
+        let Some((min_x, min_y, max_x, max_y)) = bounds else {
+            return img;
+        };
+
+        let margin = self.config.trim_margin;
+        let left = min_x.saturating_sub(margin);
+        let top = min_y.saturating_sub(margin);
+        let right = max_x.saturating_add(margin).min(width - 1);
+        let bottom = max_y.saturating_add(margin).min(height - 1);
+        if left == 0 && top == 0 && right == width - 1 && bottom == height - 1 {
+            return img;
+        }
+        img.crop_imm(left, top, right - left + 1, bottom - top + 1)
+    }
 
-    // 9. Create ndarray (H, W, C), shape is (height, width, 3)
-    let img_array = Array3::from_shape_vec(
-        (height as usize, width as usize, 3),
-        raw_pixels_f32
-    ).context("Failed to create ndarray from pixel data")?;
+    /// 等比缩放，使图片恰好放入目标尺寸（小图同样会被放大）
+    pub fn resize(&self, img: &DynamicImage) -> RgbaImage {
+        let (target_width, target_height) = self.config.size.dimensions();
+        let (orig_width, orig_height) = img.dimensions();
+        let scale = (target_width as f32 / orig_width as f32).min(target_height as f32 / orig_height as f32);
+        let new_width = ((orig_width as f32 * scale).round() as u32).clamp(1, target_width);
+        let new_height = ((orig_height as f32 * scale).round() as u32).clamp(1, target_height);
 
-    // 10. Normalize (H, W, C)
-    let mean = [0.5, 0.5, 0.5];
-    let std = [0.5, 0.5, 0.5];
+        img.resize_exact(new_width, new_height, self.config.resample.filter_type())
+            .to_rgba8()
+    }
+
+    /// 把缩放后的图片放到填充色的画布上，透明像素与填充色混合，返回 RGB 图片
+    pub fn pad(&self, resized: &RgbaImage) -> RgbImage {
+        let (target_width, target_height) = self.config.size.dimensions();
+        let [r, g, b] = self.config.pad_color;
+        let mut canvas = RgbaImage::from_pixel(target_width, target_height, Rgba([r, g, b, 255]));
+
+        let (offset_x, offset_y) = match self.config.alignment {
+            Alignment::Center => (
+                (target_width - resized.width()) / 2,
+                (target_height - resized.height()) / 2,
+            ),
+            Alignment::TopLeft => (0, 0),
+        };
+        image::imageops::overlay(&mut canvas, resized, offset_x.into(), offset_y.into());
+
+        canvas.convert()
+    }
 
-    // Create ndarrays for mean and std with shape (1, 1, 3) for broadcasting
-    let mean_array = Array3::from_shape_fn((1, 1, 3), |(_, _, c)| mean[c]);
-    let std_array = Array3::from_shape_fn((1, 1, 3), |(_, _, c)| std[c]);
+    /// 缩放像素值并按 mean / std 归一化，转为 (1, C, H, W)
+    pub fn to_tensor(&self, img: &RgbImage) -> anyhow::Result<Array4<f32>> {
+        let (width, height) = img.dimensions();
+        let rescale = if self.config.do_rescale { self.config.rescale_factor } else { 1.0 };
+        let (mean, std) = self.normalization();
 
-    // Perform normalization using element-wise operations with broadcasting
-    let normalized = (img_array - mean_array) / std_array;
+        Ok(Array4::from_shape_fn((1, 3, height as usize, width as usize), |(_, c, y, x)| {
+            let value = img.get_pixel(x as u32, y as u32)[c] as f32 * rescale;
+            (value - mean[c]) / std[c]
+        }))
+    }
 
+    /// `to_tensor` 的逆过程，把 (1, C, H, W) 的张量还原为图片，用于检查预处理结果
+    pub fn to_image(&self, tensor: ArrayView4<f32>) -> anyhow::Result<RgbImage> {
+        let (_, channels, height, width) = tensor.dim();
+        if channels != 3 {
+            anyhow::bail!("Expected 3 channels, got {}", channels);
+        }
+        let rescale = if self.config.do_rescale { self.config.rescale_factor } else { 1.0 };
+        let (mean, std) = self.normalization();
 
-    // 11. Rearrange dimensions to (C, H, W)
-    let mut channels_first: ArrayBase<OwnedRepr<f32>, Dim<[usize; 3]>> = Array3::<f32>::zeros((3, height as usize, width as usize));
-    for h in 0..height as usize {
-        for w in 0..width as usize {
-            for c in 0..3 {
-                channels_first[[c, h, w]] = normalized[[h, w, c]];
+        let mut bytes = Vec::with_capacity(height * width * 3);
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..3 {
+                    let value = (tensor[[0, c, y, x]] * std[c] + mean[c]) / rescale;
+                    bytes.push(value.round().clamp(0.0, 255.0) as u8);
+                }
             }
         }
+        ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(width as u32, height as u32, bytes)
+            .context("Failed to create RgbImage from raw bytes")
     }
 
-    _save_image_from_array(&channels_first, _save_path)?;
+    pub fn save_image(&self, tensor: ArrayView4<f32>, file_path: &Path) -> anyhow::Result<()> {
+        self.to_image(tensor)?
+            .save(file_path)
+            .with_context(|| format!("Failed to save image to {}", file_path.display()))
+    }
 
-    // 11.5 Add batch dimension: (1, C, H, W)
-    let batched: ArrayBase<OwnedRepr<f32>, Dim<[usize; 4]>> = channels_first.insert_axis(Axis(0));
+    /// 关闭归一化时 mean 为 0、std 为 1
+    fn normalization(&self) -> ([f32; 3], [f32; 3]) {
+        if self.config.do_normalize {
+            (self.config.image_mean, self.config.image_std)
+        } else {
+            ([0.0; 3], [1.0; 3])
+        }
+    }
+}
 
-    Ok(batched)
+/// 不透明且至少一个通道与背景色相差超过 `tolerance` 的像素
+fn is_foreground(pixel: &Rgba<u8>, background: [u8; 3], tolerance: u8) -> bool {
+    pixel[3] > 0 && (0..3).any(|c| pixel[c].abs_diff(background[c]) > tolerance)
 }
 
-fn _save_image_from_array(array: &Array3<f32>, file_path: &str) -> anyhow::Result<()> {
-    // Input array is (C, H, W) normalized
+/// ITU-R BT.601 亮度
+fn luminance([r, g, b]: [u8; 3]) -> f32 {
+    0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+}
 
-    let mean = [0.5, 0.5, 0.5];
-    let std = [0.5, 0.5, 0.5];
+/// 图片边框上不透明像素各通道的中位数，作为背景色的估计；边框完全透明时返回 None
+fn border_color(img: &RgbaImage) -> Option<[u8; 3]> {
+    let (width, height) = img.dimensions();
+    let mut channels: [Vec<u8>; 3] = Default::default();
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let on_border = x == 0 || y == 0 || x + 1 == width || y + 1 == height;
+        if on_border && pixel[3] > 0 {
+            for (c, values) in channels.iter_mut().enumerate() {
+                values.push(pixel[c]);
+            }
+        }
+    }
+    if channels[0].is_empty() {
+        return None;
+    }
+    Some(channels.map(|mut values| {
+        values.sort_unstable();
+        values[values.len() / 2]
+    }))
+}
+
+/// 使用 `PreprocessConfig` 的默认参数预处理
+pub fn process_image_with_padding(img: DynamicImage) -> anyhow::Result<Array4<f32>> {
+    Preprocessor::default().process(img)
+}
 
-    let (c, h, w) = (array.dim().0, array.dim().1, array.dim().2);
-    if c != 3 {
-        return Err(anyhow::anyhow!("Input array to save_image_from_array must have 3 channels"));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 背景为 `background` 的图片，(x0, y0) 到 (x1, y1)（含）之间填充 `foreground`
+    fn rect_image(width: u32, height: u32, background: [u8; 3], foreground: [u8; 3], (x0, y0, x1, y1): (u32, u32, u32, u32)) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let inside = (x0..=x1).contains(&x) && (y0..=y1).contains(&y);
+            Rgb(if inside { foreground } else { background })
+        }))
     }
 
-    // 1. Denormalize the image and convert to HWC (RGB) Vec<u8>
-    let mut denormalized_img_bytes: Vec<u8> = Vec::with_capacity(h * w * 3);
+    #[test]
+    fn trim_crops_to_content_with_margin() {
+        let preprocessor = Preprocessor::new(PreprocessConfig { do_trim: true, trim_margin: 2, ..Default::default() });
+        let img = rect_image(20, 10, [255; 3], [0; 3], (8, 4, 11, 5));
+        let trimmed = preprocessor.trim(img);
+        assert_eq!(trimmed.dimensions(), (8, 6));
+        assert_eq!(trimmed.to_rgb8().get_pixel(2, 2), &Rgb([0; 3]));
+    }
 
-    for h in 0..h {
-        for w in 0..w {
-            for c in 0..c {
-                // Denormalize: value = (normalized_value * std) + mean
-                let pixel_f32 = array[[c, h, w]] * std[c] + mean[c];
-                // Scale back to [0, 255] and clamp, then convert to u8
-                let pixel_u8 = (pixel_f32 * 255.0).clamp(0.0, 255.0) as u8;
-                denormalized_img_bytes.push(pixel_u8);
-            }
-        }
+    #[test]
+    fn trim_ignores_colours_within_tolerance() {
+        let preprocessor = Preprocessor::new(PreprocessConfig { do_trim: true, trim_margin: 0, ..Default::default() });
+        let img = rect_image(20, 10, [255; 3], [240; 3], (8, 4, 11, 5));
+        assert_eq!(preprocessor.trim(img).dimensions(), (20, 10));
     }
 
-    // 2. Convert the denormalized HWC (RGB) data into an RgbImage
-    let img = ImageBuffer::<image::Rgb<u8>, Vec<u8>>::from_raw(w as u32, h as u32, denormalized_img_bytes)
-        .context("Failed to create RgbImage from raw bytes")?;
+    #[test]
+    fn trim_is_off_by_default() {
+        let img = rect_image(20, 10, [255; 3], [0; 3], (8, 4, 11, 5));
+        assert_eq!(Preprocessor::default().trim(img).dimensions(), (20, 10));
+    }
 
-    // 3. Save the image to a file
-    img.save(file_path).context(format!("Failed to save image to {}", file_path))?;
+    #[test]
+    fn polarity_inverts_light_text_on_dark_background() {
+        let preprocessor = Preprocessor::new(PreprocessConfig { normalize_polarity: true, ..Default::default() });
+        let dark = rect_image(10, 10, [30; 3], [220; 3], (3, 3, 6, 6));
+        let normalized = preprocessor.normalize_polarity(dark).to_rgb8();
+        assert_eq!(normalized.get_pixel(0, 0), &Rgb([225; 3]));
+        assert_eq!(normalized.get_pixel(4, 4), &Rgb([35; 3]));
 
-    Ok(())
-}
\ No newline at end of file
+        let light = rect_image(10, 10, [225; 3], [35; 3], (3, 3, 6, 6));
+        assert_eq!(preprocessor.normalize_polarity(light.clone()).to_rgb8(), light.to_rgb8());
+    }
+}
@@ -0,0 +1,188 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::logits::top_k_indices;
+
+/// 从一步的 logits 中选出下一个 token，`history` 为已生成的 token（含 BOS）
+pub trait TokenSelector {
+    fn select(&mut self, logits: &[f32], history: &[u32]) -> u32;
+
+    /// 通知选择器一个未经 `select` 而被强制输入的 token（例如用户给定的前缀）
+    fn observe(&mut self, _token_id: u32) {}
+}
+
+impl<T: TokenSelector + ?Sized> TokenSelector for Box<T> {
+    fn select(&mut self, logits: &[f32], history: &[u32]) -> u32 {
+        (**self).select(logits, history)
+    }
+
+    fn observe(&mut self, token_id: u32) {
+        (**self).observe(token_id)
+    }
+}
+
+/// 贪心解码：直接取 argmax
+pub struct GreedySelector;
+
+impl TokenSelector for GreedySelector {
+    fn select(&mut self, logits: &[f32], _history: &[u32]) -> u32 {
+        argmax(logits)
+    }
+}
+
+/// 随机采样参数
+#[derive(Debug, Clone)]
+pub struct SamplingConfig {
+    /// 温度，小于等于 0 时退化为 argmax
+    pub temperature: f32,
+    /// 只在概率最高的 k 个 token 中采样
+    pub top_k: Option<usize>,
+    /// nucleus 采样：只保留累计概率达到 p 的最小 token 集合
+    pub top_p: Option<f32>,
+    /// 对已出现过的 token 施加的惩罚系数，1.0 表示不惩罚
+    pub repetition_penalty: f32,
+    /// 随机种子，相同的种子与参数会得到相同的结果
+    pub seed: Option<u64>,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            temperature: 1.0,
+            top_k: None,
+            top_p: None,
+            repetition_penalty: 1.0,
+            seed: None,
+        }
+    }
+}
+
+/// 按 `SamplingConfig` 采样的选择器
+pub struct Sampler {
+    config: SamplingConfig,
+    rng: StdRng,
+}
+
+impl Sampler {
+    pub fn new(config: SamplingConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self { config, rng }
+    }
+}
+
+impl TokenSelector for Sampler {
+    fn select(&mut self, logits: &[f32], history: &[u32]) -> u32 {
+        let mut scores = logits.to_vec();
+        apply_repetition_penalty(&mut scores, history, self.config.repetition_penalty);
+
+        if self.config.temperature <= 0.0 {
+            return argmax(&scores);
+        }
+        for score in scores.iter_mut() {
+            *score /= self.config.temperature;
+        }
+
+        // 1. top-k 截断（未设置时保留全部词表），结果按分数从高到低排列
+        let k = self.config.top_k.filter(|&k| k > 0).unwrap_or(scores.len());
+        let candidates = top_k_indices(&scores, k);
+
+        // 2. 在候选集合上做 softmax
+        let max_score = scores[candidates[0]];
+        let mut probs: Vec<f32> = candidates.iter().map(|&i| (scores[i] - max_score).exp()).collect();
+        let total: f32 = probs.iter().sum();
+        for p in probs.iter_mut() {
+            *p /= total;
+        }
+
+        // 3. top-p 截断，至少保留一个 token
+        let mut keep = probs.len();
+        if let Some(top_p) = self.config.top_p.filter(|&p| p > 0.0 && p < 1.0) {
+            let mut cumulative = 0.0;
+            for (i, p) in probs.iter().enumerate() {
+                cumulative += p;
+                if cumulative >= top_p {
+                    keep = i + 1;
+                    break;
+                }
+            }
+        }
+
+        // 4. 按截断后的分布采样
+        let kept_total: f32 = probs[..keep].iter().sum();
+        let mut threshold = self.rng.gen::<f32>() * kept_total;
+        for (i, p) in probs[..keep].iter().enumerate() {
+            threshold -= p;
+            if threshold <= 0.0 {
+                return candidates[i] as u32;
+            }
+        }
+        candidates[keep - 1] as u32
+    }
+}
+
+fn argmax(logits: &[f32]) -> u32 {
+    logits
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(idx, _)| idx as u32)
+        .unwrap_or(0)
+}
+
+/// CTRL 风格的重复惩罚：正分数除以系数，负分数乘以系数
+fn apply_repetition_penalty(scores: &mut [f32], history: &[u32], penalty: f32) {
+    if penalty == 1.0 {
+        return;
+    }
+    let mut seen = history.to_vec();
+    seen.sort_unstable();
+    seen.dedup();
+    for token_id in seen {
+        if let Some(score) = scores.get_mut(token_id as usize) {
+            *score = if *score > 0.0 { *score / penalty } else { *score * penalty };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(sampler: &mut Sampler, logits: &[f32], n: usize) -> Vec<u32> {
+        (0..n).map(|_| sampler.select(logits, &[])).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_tokens() {
+        let logits = [0.5, 1.0, 0.2, 0.9, 0.1];
+        let config = SamplingConfig { seed: Some(42), ..Default::default() };
+        let first = draw(&mut Sampler::new(config.clone()), &logits, 32);
+        let second = draw(&mut Sampler::new(config), &logits, 32);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn top_k_limits_candidates() {
+        let logits = [3.0, 2.9, 0.0, 2.8, 0.1];
+        let mut sampler = Sampler::new(SamplingConfig { top_k: Some(2), seed: Some(7), ..Default::default() });
+        assert!(draw(&mut sampler, &logits, 200).iter().all(|&id| id == 0 || id == 1));
+    }
+
+    #[test]
+    fn top_p_keeps_smallest_nucleus() {
+        // 概率约为 0.84、0.11、0.04、0.01，top_p = 0.9 只保留前两个
+        let logits = [4.0, 2.0, 1.0, -0.5];
+        let mut sampler = Sampler::new(SamplingConfig { top_p: Some(0.9), seed: Some(7), ..Default::default() });
+        assert!(draw(&mut sampler, &logits, 200).iter().all(|&id| id == 0 || id == 1));
+    }
+
+    #[test]
+    fn zero_temperature_is_argmax_after_penalty() {
+        let logits = [2.0, 1.5, 0.0];
+        let mut sampler = Sampler::new(SamplingConfig { temperature: 0.0, repetition_penalty: 2.0, ..Default::default() });
+        assert_eq!(sampler.select(&logits, &[]), 0);
+        assert_eq!(sampler.select(&logits, &[0]), 1);
+    }
+}
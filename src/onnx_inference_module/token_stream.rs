@@ -0,0 +1,177 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::encoder_output::EncoderOutput;
+use super::generation::{GenerationOutput, StopReason, StoppingCriteria};
+use super::kv_cache::KvCache;
+use super::logits::TokenChoice;
+use super::onnx_inference::{ImageInput, OrtInferenceSession};
+use super::sampling::{GreedySelector, TokenSelector};
+
+/// 逐 token 生成的参数
+pub struct GenerateOptions {
+    /// 用户已修正的前缀 token（不含 BOS），解码从前缀之后继续
+    pub prefix: Vec<u32>,
+    pub selector: Box<dyn TokenSelector + Send>,
+    pub criteria: StoppingCriteria,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            prefix: Vec::new(),
+            selector: Box::new(GreedySelector),
+            criteria: StoppingCriteria::default(),
+        }
+    }
+}
+
+/// 生成过程中产生的事件：每选出一个 token（含 EOS）产生一个 `Token`，最后以 `Done` 结束
+#[derive(Debug, Clone)]
+pub enum TokenEvent {
+    Token(TokenChoice),
+    Done(GenerationOutput),
+}
+
+enum State {
+    /// 尚未编码图片，第一次调用 `next` 时才开始推理
+    Pending(ImageInput),
+    Decoding {
+        kv_cache: KvCache,
+        encoder_hidden_states: EncoderOutput,
+        /// 已经选出但还没有返回的 token
+        next: Option<TokenChoice>,
+    },
+    /// 已返回最后一个 token，下一次调用返回 `Done`
+    Stopping(StopReason),
+    Finished,
+}
+
+/// 逐 token 生成的迭代器，负责 EOS、重复检测、最大长度与取消。出错后返回一次 `Err` 并结束。
+pub struct TokenIter<'a> {
+    onnx_session: &'a OrtInferenceSession,
+    selector: Box<dyn TokenSelector + Send>,
+    criteria: StoppingCriteria,
+    state: State,
+    /// 以 BOS 开头，包含前缀与已生成的 token
+    token_ids: Vec<u32>,
+    prefix: Vec<u32>,
+    eos_token_id: u32,
+    generated: usize,
+    start: Instant,
+    first_token_time: Duration,
+}
+
+impl<'a> TokenIter<'a> {
+    fn new(onnx_session: &'a OrtInferenceSession, input_image: ImageInput, options: GenerateOptions) -> Self {
+        let tokenizer = onnx_session.get_tokenizer();
+        let eos_token_id = tokenizer.token_to_id("</s>").unwrap_or(30000);
+        let bos_token_id = tokenizer.token_to_id("<s>").unwrap_or(0);
+        let mut token_ids = vec![bos_token_id];
+        token_ids.extend_from_slice(&options.prefix);
+        Self {
+            onnx_session,
+            selector: options.selector,
+            criteria: options.criteria,
+            state: State::Pending(input_image),
+            token_ids,
+            prefix: options.prefix,
+            eos_token_id,
+            generated: 0,
+            start: Instant::now(),
+            first_token_time: Duration::ZERO,
+        }
+    }
+
+    fn finish(&mut self, stop_reason: StopReason) -> TokenEvent {
+        self.state = State::Finished;
+        TokenEvent::Done(GenerationOutput {
+            token_ids: std::mem::take(&mut self.token_ids),
+            stop_reason,
+            first_token_time: self.first_token_time,
+            total_time: self.start.elapsed(),
+        })
+    }
+
+    fn step(&mut self) -> anyhow::Result<TokenEvent> {
+        match std::mem::replace(&mut self.state, State::Finished) {
+            State::Pending(input_image) => {
+                let (kv_cache, choice, encoder_hidden_states) =
+                    self.onnx_session.init_inference(input_image, &self.prefix, self.selector.as_mut())?;
+                self.first_token_time = self.start.elapsed();
+                self.state = State::Decoding { kv_cache, encoder_hidden_states, next: Some(choice) };
+                self.step()
+            }
+            State::Decoding { mut kv_cache, encoder_hidden_states, next } => {
+                if self.criteria.is_cancelled() {
+                    return Ok(self.finish(StopReason::Cancelled));
+                }
+                let choice = match next {
+                    Some(choice) => choice,
+                    None => {
+                        let last_token_id = *self.token_ids.last().unwrap_or(&0);
+                        let (present, choice) = self.onnx_session.single_inference(
+                            &kv_cache,
+                            last_token_id,
+                            &encoder_hidden_states,
+                            self.selector.as_mut(),
+                            &self.token_ids,
+                        )?;
+                        kv_cache = present;
+                        choice
+                    }
+                };
+
+                self.generated += 1;
+                self.token_ids.push(choice.token_id);
+                self.state = match self.criteria.stop_reason(&self.token_ids, self.generated, self.eos_token_id) {
+                    // 重复片段中的最后一个 token 不再返回
+                    Some(StopReason::Repetition) => return Ok(self.finish(StopReason::Repetition)),
+                    Some(stop_reason) => State::Stopping(stop_reason),
+                    None => State::Decoding { kv_cache, encoder_hidden_states, next: None },
+                };
+                Ok(TokenEvent::Token(choice))
+            }
+            State::Stopping(stop_reason) => Ok(self.finish(stop_reason)),
+            State::Finished => anyhow::bail!("Generation already finished"),
+        }
+    }
+}
+
+impl Iterator for TokenIter<'_> {
+    type Item = anyhow::Result<TokenEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if matches!(self.state, State::Finished) {
+            return None;
+        }
+        Some(self.step())
+    }
+}
+
+impl OrtInferenceSession {
+    /// 返回逐 token 生成的迭代器，图片的预处理与编码在第一次调用 `next` 时进行
+    pub fn generate(&self, input_image: impl Into<ImageInput>, options: GenerateOptions) -> TokenIter<'_> {
+        TokenIter::new(self, input_image.into(), options)
+    }
+
+    /// `generate` 的异步版本，在 tokio 的阻塞线程中解码。Stream 被丢弃后解码在下一步停止。
+    /// 必须在 tokio 运行时中调用。
+    pub fn generate_stream(
+        self: Arc<Self>,
+        input_image: impl Into<ImageInput> + Send + 'static,
+        options: GenerateOptions,
+    ) -> impl Stream<Item = anyhow::Result<TokenEvent>> + Send + 'static {
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        tokio::task::spawn_blocking(move || {
+            for event in self.generate(input_image, options) {
+                if tx.blocking_send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+}
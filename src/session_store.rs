@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::temporary_img::TemporaryData;
+
+/// 会话存储的容量与过期配置
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// 会话在最后一次访问后保留的时长
+    pub ttl: Duration,
+    /// 同时保留的会话数量上限，超出时淘汰最久未访问的会话
+    pub max_sessions: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(30 * 60),
+            max_sessions: 64,
+        }
+    }
+}
+
+struct SessionEntry {
+    data: Arc<Mutex<TemporaryData>>,
+    last_access: Instant,
+}
+
+/// 按客户端会话 id 隔离的临时数据存储
+pub struct SessionStore {
+    config: SessionConfig,
+    sessions: Mutex<HashMap<String, SessionEntry>>,
+}
+
+impl SessionStore {
+    pub fn new(config: SessionConfig) -> Self {
+        Self {
+            config,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 新建一个保存 `data` 的会话并返回会话 id
+    pub fn create(&self, data: TemporaryData) -> anyhow::Result<String> {
+        let mut sessions = self.lock()?;
+        let now = Instant::now();
+        Self::prune_locked(&mut sessions, self.config.ttl, now);
+
+        // 达到上限时淘汰最久未访问的会话
+        while !sessions.is_empty() && sessions.len() >= self.config.max_sessions {
+            let oldest = sessions
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_access)
+                .map(|(id, _)| id.clone());
+            match oldest {
+                Some(id) => sessions.remove(&id),
+                None => break,
+            };
+        }
+
+        let session_id = uuid::Uuid::new_v4().simple().to_string();
+        sessions.insert(
+            session_id.clone(),
+            SessionEntry {
+                data: Arc::new(Mutex::new(data)),
+                last_access: now,
+            },
+        );
+        Ok(session_id)
+    }
+
+    /// 返回未过期会话的数据，并刷新其过期时间
+    pub fn get(&self, session_id: &str) -> Option<Arc<Mutex<TemporaryData>>> {
+        let mut sessions = self.lock().ok()?;
+        let now = Instant::now();
+        let ttl = self.config.ttl;
+        let entry = sessions.get_mut(session_id)?;
+        if now.duration_since(entry.last_access) > ttl {
+            sessions.remove(session_id);
+            return None;
+        }
+        entry.last_access = now;
+        Some(Arc::clone(&entry.data))
+    }
+
+    /// 移除所有超过 TTL 未访问的会话，返回移除的数量
+    pub fn prune_expired(&self) -> usize {
+        match self.lock() {
+            Ok(mut sessions) => Self::prune_locked(&mut sessions, self.config.ttl, Instant::now()),
+            Err(_) => 0,
+        }
+    }
+
+    fn prune_locked(sessions: &mut HashMap<String, SessionEntry>, ttl: Duration, now: Instant) -> usize {
+        let before = sessions.len();
+        sessions.retain(|_, entry| now.duration_since(entry.last_access) <= ttl);
+        before - sessions.len()
+    }
+
+    fn lock(&self) -> anyhow::Result<std::sync::MutexGuard<'_, HashMap<String, SessionEntry>>> {
+        self.sessions
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Session store lock poisoned"))
+    }
+}
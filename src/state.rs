@@ -1,15 +1,111 @@
-use std::sync::{Arc, Mutex};
-use crate::onnx_inference_module::{OrtInferenceSession, TemporaryData};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use mixtex::{BatchScheduler, ImageInput, OrtInferenceSession};
+use crate::decode::DecodeDefaults;
+use crate::session_store::SessionStore;
+use crate::job_registry::JobRegistry;
+use crate::executor::InferenceExecutor;
+use crate::config::AppConfig;
+use crate::i18n::Locale;
 
 pub struct AppStore {
     pub onnx_session: Arc<OrtInferenceSession>,
-    pub temporary_data: Arc<Mutex<TemporaryData>>,
+    pub sessions: Arc<SessionStore>,
+    pub batcher: Arc<BatchScheduler>,
+    pub jobs: Arc<JobRegistry>,
+    pub executor: Arc<InferenceExecutor>,
+    /// 配置文件中的默认解码参数
+    pub decode_defaults: DecodeDefaults,
+    /// 请求没有给出支持的 `Accept-Language` 时使用的语言
+    pub default_locale: Locale,
+    /// 预处理结果的保存目录，未配置时不保存
+    pub debug_dump_dir: Option<PathBuf>,
+    /// 保存目录中最多保留的任务数
+    pub debug_max_dumps: usize,
 }
 
 impl AppStore {
-    pub fn new(model_folder: &str, tokenizer_path: &str) -> anyhow::Result<Self> {
-        let onnx_session = Arc::new(OrtInferenceSession::new(model_folder, tokenizer_path)?);
-        let temporary_data = Arc::new(Mutex::new(TemporaryData::new()));
-        Ok(Self { onnx_session, temporary_data })
+    pub fn new(config: &AppConfig) -> anyhow::Result<Self> {
+        let onnx_session = Arc::new(OrtInferenceSession::new(&config.model.folder, &config.model.tokenizer, config.model.intra_threads)?);
+        let sessions = Arc::new(SessionStore::new(config.session.session_config()));
+        let batcher = Arc::new(BatchScheduler::spawn(Arc::clone(&onnx_session), config.inference.batch_config())?);
+        let jobs = Arc::new(JobRegistry::default());
+        let executor = Arc::new(InferenceExecutor::new(config.inference.executor_config())?);
+        let decode_defaults = config.decode.clone();
+        let default_locale = config.server.locale;
+        let debug_dump_dir = config.debug.dump_dir.clone();
+        let debug_max_dumps = config.debug.max_dumps;
+        Ok(Self { onnx_session, sessions, batcher, jobs, executor, decode_defaults, default_locale, debug_dump_dir, debug_max_dumps })
     }
-}
\ No newline at end of file
+
+    /// 预处理图片，返回编码器实际使用的张量。配置了 `debug.dump_dir` 时把该张量还原为图片，
+    /// 写到该任务自己的目录中，并删除超出 `debug.max_dumps` 的最早的任务目录；保存失败不影响推理
+    pub fn prepare_input(&self, job_id: &str, image: image::DynamicImage) -> anyhow::Result<ImageInput> {
+        let pixel_values = self.onnx_session.preprocess_image(image)?;
+        if let Some(dump_dir) = &self.debug_dump_dir {
+            let result = (|| -> anyhow::Result<()> {
+                let job_dir = dump_dir.join(format!("{}{}", DUMP_DIR_PREFIX, job_id));
+                std::fs::create_dir_all(&job_dir)?;
+                let tensor = pixel_values.view().into_dimensionality()?;
+                self.onnx_session.preprocessor().save_image(tensor, &job_dir.join("preprocessed.png"))?;
+                prune_dumps(dump_dir, self.debug_max_dumps)
+            })();
+            if let Err(e) = result {
+                eprintln!("Failed to dump preprocessed image for job {}: {:#}", job_id, e);
+            }
+        }
+        Ok(ImageInput::Pixels(pixel_values))
+    }
+}
+
+/// 任务目录名的前缀，清理时只处理带有该前缀的目录
+const DUMP_DIR_PREFIX: &str = "job-";
+
+/// 按修改时间只保留最新的 `max_dumps` 个任务目录。`dump_dir` 可能是已有的目录，
+/// 其中不是由服务创建的（名称不以 `DUMP_DIR_PREFIX` 开头的）条目不会被删除
+fn prune_dumps(dump_dir: &Path, max_dumps: usize) -> anyhow::Result<()> {
+    let mut job_dirs = Vec::new();
+    for entry in std::fs::read_dir(dump_dir)? {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with(DUMP_DIR_PREFIX) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            job_dirs.push((metadata.modified()?, entry.path()));
+        }
+    }
+    if job_dirs.len() <= max_dumps {
+        return Ok(());
+    }
+    job_dirs.sort();
+    for (_, path) in &job_dirs[..job_dirs.len() - max_dumps] {
+        std::fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_keeps_unrelated_directories() {
+        let dump_dir = std::env::temp_dir().join(format!("mixtex-prune-{}", uuid::Uuid::new_v4().simple()));
+        for name in ["job-a", "job-b", "job-c", "models", "other"] {
+            std::fs::create_dir_all(dump_dir.join(name)).unwrap();
+            // 保证修改时间有先后
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        std::fs::write(dump_dir.join("job-file"), b"").unwrap();
+
+        prune_dumps(&dump_dir, 1).unwrap();
+        let mut remaining: Vec<String> = std::fs::read_dir(&dump_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        remaining.sort();
+        std::fs::remove_dir_all(&dump_dir).unwrap();
+        assert_eq!(remaining, ["job-c", "job-file", "models", "other"]);
+    }
+}
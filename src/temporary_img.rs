@@ -5,6 +5,12 @@ pub struct TemporaryData {
     pub token_id_array: Vec<u32>,
 }
 
+impl Default for TemporaryData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[allow(dead_code)]
 #[allow(unused_imports)]
 impl TemporaryData {
@@ -16,6 +22,11 @@ impl TemporaryData {
         Self { image, token_id_array }
     }
 
+    /// Creates a TemporaryData instance holding an uploaded image.
+    pub fn with_image(image: DynamicImage) -> Self {
+        Self { image, token_id_array: Vec::new() }
+    }
+
     /// Returns a reference to the image.
     pub fn get_image(&self) -> Option<&DynamicImage> {
         Some(&self.image)